@@ -0,0 +1,82 @@
+#![allow(non_snake_case)]
+// A local stand-in for DCS-BIOS, the bridge connects to it like to DCS.
+// Used by the export replay and the mock server.
+
+use anyhow::{Context, Result};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Ipv4Addr, Shutdown, TcpListener, TcpStream, UdpSocket};
+use std::sync::Arc;
+use std::thread;
+
+use crate::dcsBiosHelper::{BiosConnection, BiosPorts, DCS_HOST, MULTICAST_GROUP};
+
+// Sends export data until sending fails
+pub(crate) type ExportSource = Arc<dyn Fn(&mut dyn FnMut(&[u8]) -> io::Result<()>) -> io::Result<()> + Send + Sync>;
+// Gets every command line a client sends
+pub(crate) type CommandSink = Arc<dyn Fn(&str) + Send + Sync>;
+
+// TCP: every client gets its own run of the export and sends commands on the
+// same connection. UDP: the export goes to the multicast group, commands come
+// in as datagrams. Runs until the process ends. Returns the ports it listens
+// on, a port of 0 is replaced by the one the system picked.
+pub(crate) fn serve_export(name: &'static str, connection: BiosConnection, ports: BiosPorts, export: ExportSource, on_command: CommandSink) -> Result<BiosPorts> {
+    let mut bound = ports;
+    match connection {
+        BiosConnection::Tcp => {
+            let listener = TcpListener::bind((DCS_HOST, ports.tcp)).with_context(|| format!("{}: failed to listen on port {}", name, ports.tcp))?;
+            bound.tcp = listener.local_addr()?.port();
+            println!("{}: serving on {}:{}", name, DCS_HOST, bound.tcp);
+            thread::spawn(move || {
+                // the bridge sends its commands on a connection of their own
+                for mut stream in listener.incoming().flatten() {
+                    read_tcp_commands(&stream, on_command.clone());
+                    let export = export.clone();
+                    thread::spawn(move || {
+                        let _ = export(&mut |data| stream.write_all(data));
+                        // the command reader holds a clone, the client has to see the end
+                        let _ = stream.shutdown(Shutdown::Both);
+                    });
+                }
+            });
+        }
+        BiosConnection::Udp => {
+            let commands = UdpSocket::bind((DCS_HOST, ports.command)).with_context(|| format!("{}: failed to listen on port {}", name, ports.command))?;
+            bound.command = commands.local_addr()?.port();
+            thread::spawn(move || read_udp_commands(commands, on_command));
+
+            let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+            // the bridge on this machine has to hear it
+            socket.set_multicast_loop_v4(true)?;
+            socket.set_multicast_ttl_v4(1)?;
+            println!("{}: sending to {}:{}", name, MULTICAST_GROUP, ports.export);
+            thread::spawn(move || {
+                if let Err(e) = export(&mut |data| socket.send_to(data, (MULTICAST_GROUP, ports.export)).map(|_| ())) {
+                    eprintln!("{}: {}", name, e);
+                }
+            });
+        }
+    }
+    Ok(bound)
+}
+
+fn read_tcp_commands(stream: &TcpStream, on_command: CommandSink) {
+    let Ok(stream) = stream.try_clone() else { return };
+    thread::spawn(move || {
+        for line in BufReader::new(stream).lines() {
+            match line {
+                Ok(line) => on_command(&line),
+                Err(_) => break,
+            }
+        }
+    });
+}
+
+fn read_udp_commands(socket: UdpSocket, on_command: CommandSink) {
+    let mut buffer = [0u8; 1024];
+    while let Ok(n) = socket.recv(&mut buffer) {
+        // one or more newline terminated commands per datagram
+        for line in String::from_utf8_lossy(&buffer[..n]).lines() {
+            on_command(line);
+        }
+    }
+}
@@ -0,0 +1,307 @@
+#![allow(non_snake_case)]
+// Reads HID packets out of the different capture formats we have lying around,
+// so any capture can be replayed as an init sequence:
+//   Hex       one packet per line, "f000022a31bb..." (PFP3N.txt, PFP7.txt, the
+//             payloads exported out of Wireshark, no USB header left to strip)
+//   Python    device.write([0xf0, 0x0, ...]) (init.txt, init3.txt)
+//   Wireshark hex + ASCII dumps with offsets, one USBPcap frame per block,
+//             blocks separated by blank lines (custom_font.txt, PFP7 wireshark.txt)
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::fs;
+
+// USBPcap header fields, relative to the start of a frame
+const USBPCAP_INFO: usize = 16;
+const USBPCAP_INFO_IN: u8 = 0x01; // set on device -> host transfers
+const USBPCAP_DATA_LEN: usize = 23;
+const USBPCAP_MIN_HEADER: usize = 27;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum CaptureFormat {
+    Hex,
+    Python,
+    Wireshark
+}
+
+// Decides on the first line that isn't empty or a comment
+pub(crate) fn detect_format(text: &str) -> CaptureFormat {
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        if trimmed.starts_with("device.write") {
+            return CaptureFormat::Python;
+        }
+        if parse_dump_line(trimmed).is_some() {
+            return CaptureFormat::Wireshark;
+        }
+        return CaptureFormat::Hex;
+    }
+    CaptureFormat::Hex
+}
+
+pub(crate) fn load_init_packets(path: &str) -> Result<Vec<Vec<u8>>> {
+    let text = fs::read_to_string(path).with_context(|| format!("Failed to open init file {}", path))?;
+    let format = detect_format(&text);
+    let packets = match format {
+        CaptureFormat::Hex => parse_hex_lines(&text),
+        CaptureFormat::Python => parse_python_writes(&text),
+        CaptureFormat::Wireshark => parse_wireshark_dump(&text),
+    };
+    packets.with_context(|| format!("Invalid init file {} ({:?} format)", path, format))
+}
+
+fn parse_hex_bytes(hex: &str, line_no: usize) -> Result<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        bail!("Line {}: odd number of hex digits: '{}'", line_no, hex);
+    }
+    let mut bytes = Vec::with_capacity(hex.len() / 2);
+    for i in (0..hex.len()).step_by(2) {
+        let pair = hex.get(i..i + 2).ok_or_else(|| anyhow!("Line {}: invalid hex '{}'", line_no, hex))?;
+        let byte = u8::from_str_radix(pair, 16)
+            .with_context(|| format!("Line {}: invalid hex pair '{}'", line_no, pair))?;
+        bytes.push(byte);
+    }
+    Ok(bytes)
+}
+
+fn parse_hex_lines(text: &str) -> Result<Vec<Vec<u8>>> {
+    let mut packets = Vec::new();
+    for (lineno, line) in text.lines().enumerate() {
+        // Strip comments and whitespace, allows "AA BB CC" or "AABBCC" styles.
+        let trimmed = line.split('#').next().unwrap_or("").trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+        let hex: String = trimmed.split_whitespace().collect();
+        packets.push(parse_hex_bytes(&hex, lineno + 1)?);
+    }
+    Ok(packets)
+}
+
+fn parse_python_writes(text: &str) -> Result<Vec<Vec<u8>>> {
+    let mut packets = Vec::new();
+    for (lineno, line) in text.lines().enumerate() {
+        let line_no = lineno + 1;
+        let trimmed = line.split('#').next().unwrap_or("").trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+        let list = trimmed
+            .strip_prefix("device.write(")
+            .and_then(|rest| rest.trim_end().strip_suffix(')'))
+            .map(str::trim)
+            .and_then(|rest| rest.strip_prefix('['))
+            .and_then(|rest| rest.strip_suffix(']'))
+            .ok_or_else(|| anyhow!("Line {}: expected device.write([...]), got '{}'", line_no, trimmed))?;
+
+        let mut bytes = Vec::new();
+        for (i, item) in list.split(',').enumerate() {
+            let item = item.trim();
+            if item.is_empty() {
+                continue;
+            }
+            let value = match item.strip_prefix("0x").or_else(|| item.strip_prefix("0X")) {
+                Some(hex) => u8::from_str_radix(hex, 16),
+                None => item.parse::<u8>(),
+            };
+            let byte = value.with_context(|| format!("Line {}: byte {} '{}' is not a byte value", line_no, i, item))?;
+            bytes.push(byte);
+        }
+        packets.push(bytes);
+    }
+    Ok(packets)
+}
+
+// "0010  00 01 00 22 00 02 01 40 00 00 00 f0 00 02 2a 31   ...\"...@......*1"
+// -> (0x10, [0x00, 0x01, ...])
+fn parse_dump_line(line: &str) -> Option<(usize, Vec<u8>)> {
+    let (offset, rest) = line.split_once("  ")?;
+    if offset.len() < 4 || !offset.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let offset = usize::from_str_radix(offset, 16).ok()?;
+    // The ASCII column is separated by at least three spaces
+    let hex_part = rest.split("   ").next()?;
+    let mut bytes = Vec::new();
+    for token in hex_part.split_whitespace() {
+        if token.len() != 2 {
+            return None;
+        }
+        bytes.push(u8::from_str_radix(token, 16).ok()?);
+    }
+    if bytes.is_empty() {
+        return None;
+    }
+    Some((offset, bytes))
+}
+
+// Strips the USBPcap header of a frame, returns None for device -> host transfers
+fn frame_payload(frame: &[u8], line_no: usize) -> Result<Option<Vec<u8>>> {
+    if frame.len() < USBPCAP_MIN_HEADER {
+        bail!("Line {}: frame of {} bytes is shorter than the USBPcap header", line_no, frame.len());
+    }
+    let header_len = u16::from_le_bytes([frame[0], frame[1]]) as usize;
+    if header_len < USBPCAP_MIN_HEADER || header_len > frame.len() {
+        bail!("Line {}: invalid USBPcap header length {}", line_no, header_len);
+    }
+    if frame[USBPCAP_INFO] & USBPCAP_INFO_IN != 0 {
+        return Ok(None);
+    }
+    let data_len = u32::from_le_bytes(frame[USBPCAP_DATA_LEN..USBPCAP_DATA_LEN + 4].try_into().unwrap()) as usize;
+    let payload = &frame[header_len..];
+    if payload.len() != data_len {
+        bail!("Line {}: USBPcap header says {} data bytes, frame has {}", line_no, data_len, payload.len());
+    }
+    if payload.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(payload.to_vec()))
+}
+
+fn parse_wireshark_dump(text: &str) -> Result<Vec<Vec<u8>>> {
+    let mut packets = Vec::new();
+    let mut frame: Vec<u8> = Vec::new();
+    let mut frame_line = 0;
+
+    for (lineno, line) in text.lines().enumerate() {
+        let line_no = lineno + 1;
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            if !frame.is_empty() {
+                packets.extend(frame_payload(&frame, frame_line)?);
+                frame.clear();
+            }
+            continue;
+        }
+        let (offset, bytes) = parse_dump_line(trimmed)
+            .ok_or_else(|| anyhow!("Line {}: expected '<offset>  <hex bytes>  <ascii>', got '{}'", line_no, trimmed))?;
+        if offset == 0 && !frame.is_empty() {
+            // frames without a blank line in between
+            packets.extend(frame_payload(&frame, frame_line)?);
+            frame.clear();
+        }
+        if offset != frame.len() {
+            bail!("Line {}: offset {:04x} doesn't follow the previous line, expected {:04x}", line_no, offset, frame.len());
+        }
+        if frame.is_empty() {
+            frame_line = line_no;
+        }
+        frame.extend(bytes);
+    }
+    if !frame.is_empty() {
+        packets.extend(frame_payload(&frame, frame_line)?);
+    }
+    Ok(packets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // USBPcap frame as Windows captures it: 27 byte header, then the HID report
+    fn usbpcap_frame(info: u8, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0u8; USBPCAP_MIN_HEADER];
+        frame[0] = USBPCAP_MIN_HEADER as u8;
+        frame[USBPCAP_INFO] = info;
+        frame[USBPCAP_DATA_LEN..USBPCAP_DATA_LEN + 4].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    // Hex + ASCII lines the way Wireshark copies a frame
+    fn dump(frame: &[u8]) -> String {
+        frame.chunks(16).enumerate().map(|(i, line)| {
+            let hex: Vec<String> = line.iter().map(|b| format!("{:02x}", b)).collect();
+            let ascii: String = line.iter().map(|&b| if b.is_ascii_graphic() { b as char } else { '.' }).collect();
+            format!("{:04x}  {}   {}\n", i * 16, hex.join(" "), ascii)
+        }).collect()
+    }
+
+    fn error(result: Result<Vec<Vec<u8>>>) -> String {
+        format!("{:#}", result.unwrap_err())
+    }
+
+    #[test]
+    fn formats_are_told_apart_by_the_first_line() {
+        assert_eq!(detect_format("f0 00 01\n"), CaptureFormat::Hex);
+        assert_eq!(detect_format("\n# comment\n  device.write([0xf0])\n"), CaptureFormat::Python);
+        assert_eq!(detect_format("0000  1b 00 10 d0   ....\n"), CaptureFormat::Wireshark);
+        assert_eq!(detect_format(""), CaptureFormat::Hex);
+    }
+
+    #[test]
+    fn captures_in_the_repo_are_detected() {
+        for (path, format) in [
+            ("PFP3N.txt", CaptureFormat::Hex),
+            ("PFP7.txt", CaptureFormat::Hex),
+            ("init.txt", CaptureFormat::Python),
+            ("init3.txt", CaptureFormat::Python),
+            ("custom_font.txt", CaptureFormat::Wireshark),
+            ("PFP7 wireshark.txt", CaptureFormat::Wireshark),
+        ] {
+            assert_eq!(detect_format(&fs::read_to_string(path).unwrap()), format, "{}", path);
+            assert!(!load_init_packets(path).unwrap().is_empty(), "{}", path);
+        }
+    }
+
+    #[test]
+    fn hex_lines_with_spaces_and_comments() {
+        let packets = parse_hex_lines("# init\nf0 00 01\n\nF00002 # second\n").unwrap();
+        assert_eq!(packets, [vec![0xf0, 0x00, 0x01], vec![0xf0, 0x00, 0x02]]);
+    }
+
+    #[test]
+    fn hex_errors_name_the_line() {
+        assert_eq!(error(parse_hex_lines("f000\n\nf00\n")), "Line 3: odd number of hex digits: 'f00'");
+        assert!(error(parse_hex_lines("f000\nzz00\n")).starts_with("Line 2: invalid hex pair 'zz'"));
+    }
+
+    #[test]
+    fn python_writes_in_hex_and_decimal() {
+        let packets = parse_python_writes("device.write([0xf0, 0X0, 1, 255,])\n# skipped\n  device.write( [0x02] )\n").unwrap();
+        assert_eq!(packets, [vec![0xf0, 0x00, 0x01, 0xff], vec![0x02]]);
+    }
+
+    #[test]
+    fn python_errors_name_the_line() {
+        assert!(error(parse_python_writes("device.write([0xf0])\ndevice.write([0xf0, 256])\n"))
+            .starts_with("Line 2: byte 1 '256' is not a byte value"));
+        assert!(error(parse_python_writes("device.write([0xf0])\n\nwrite([0xf0])\n"))
+            .starts_with("Line 3: expected device.write([...])"));
+    }
+
+    #[test]
+    fn wireshark_frames_lose_their_usbpcap_header() {
+        let text = dump(&usbpcap_frame(0, &[0xf0, 0x00, 0x01])) + "\n" + &dump(&usbpcap_frame(0, &[0x02; 20]));
+        assert_eq!(parse_wireshark_dump(&text).unwrap(), [vec![0xf0, 0x00, 0x01], vec![0x02; 20]]);
+    }
+
+    #[test]
+    fn wireshark_skips_in_transfers_and_empty_frames() {
+        // key reports from the device, and the URB completions without data
+        let text = dump(&usbpcap_frame(USBPCAP_INFO_IN, &[0x01; 12])) + &dump(&usbpcap_frame(0, &[])) + &dump(&usbpcap_frame(0, &[0xf0]));
+        assert_eq!(parse_wireshark_dump(&text).unwrap(), [vec![0xf0]]);
+    }
+
+    #[test]
+    fn wireshark_errors_name_the_line() {
+        // the second frame starts on line 4
+        let mut short = usbpcap_frame(0, &[0xf0, 0x00]);
+        short[USBPCAP_DATA_LEN] = 5;
+        let text = dump(&usbpcap_frame(0, &[0xf0])) + "\n" + &dump(&short);
+        assert_eq!(error(parse_wireshark_dump(&text)), "Line 4: USBPcap header says 5 data bytes, frame has 2");
+
+        assert_eq!(error(parse_wireshark_dump("0000  1b 00 10\n")), "Line 1: frame of 3 bytes is shorter than the USBPcap header");
+
+        let mut bad_header = usbpcap_frame(0, &[0xf0]);
+        bad_header[0] = 0x40;
+        assert_eq!(error(parse_wireshark_dump(&dump(&bad_header))), "Line 1: invalid USBPcap header length 64");
+
+        let gap = "0000  1b 00 10 d0 2e 7d 02 bd ff ff 00 00 00 00 09 00   ................\n0020  00   .\n";
+        assert_eq!(error(parse_wireshark_dump(gap)), "Line 2: offset 0020 doesn't follow the previous line, expected 0010");
+
+        assert!(error(parse_wireshark_dump("0000  1b 00\nnot a dump line\n")).starts_with("Line 2: expected '<offset>  <hex bytes>  <ascii>'"));
+    }
+}
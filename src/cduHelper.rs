@@ -0,0 +1,591 @@
+#![allow(non_snake_case)]
+#![allow(unused_parens)]
+// One display and input pipeline per attached unit
+
+use anyhow::{bail, Result};
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::dcsBiosHelper::is_connected;
+use crate::gestureHelper::{Gesture, GestureDetector, GestureKind};
+use crate::inputHelper::{KeyEvent, KeyState};
+use crate::memoryImageHelper::{snapshot, MemoryImage};
+use crate::moduleDataProcessorHelper::{get_A10C2_text, get_AH64D_text, get_AV8B_text, get_CH47F_text, get_module_name, get_status_text, handle_aircraft_input, subscribe_AH64D_page, AH64D_isCpg, HeldKeys};
+use crate::screenHelper::Screen;
+use crate::searchModeHelper::get_search_mode_disp;
+use crate::subscriptionHelper::{subscribe_frames, BiosEvent};
+use crate::transportHelper::Transport;
+use crate::writeHelper::{send_init_packets, DisplayWriter};
+use crate::{MANUAL_BUTTON_MAPPING, THREAD_SLEEP, TOGGLE_DELAY, WRITE_DELAY_LONG, WRITE_DELAY_SHORT};
+
+// What a unit shows, so two panels in one pit don't mirror each other
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Role {
+    // page of the aircraft in DCS, in the Apache the KU of the seat you're in
+    Auto,
+    // Apache KU/EUFD of a fixed seat, other aircraft as Auto
+    Ah64dPilot,
+    Ah64dCpg,
+    // RWR search page only
+    Reference
+}
+
+impl Role {
+    pub(crate) fn parse(name: &str) -> Result<Role> {
+        match name.trim().to_lowercase().as_str() {
+            "auto" => Ok(Role::Auto),
+            "apache-pilot" | "plt" => Ok(Role::Ah64dPilot),
+            "apache-cpg" | "cpg" => Ok(Role::Ah64dCpg),
+            "reference" | "ref" => Ok(Role::Reference),
+            other => bail!("Unknown role '{}', expected auto, apache-pilot, apache-cpg or reference", other),
+        }
+    }
+}
+
+pub(crate) struct Cdu {
+    role: Role,
+    device: Box<dyn Transport>,
+    keys: Arc<KeyState>,
+    gestures: GestureDetector,
+    // momentary keys that are down in DCS
+    held: HeldKeys,
+    display: DisplayWriter,
+    init_packets: Vec<Vec<u8>>,
+    // generation of the device the init packets went to, None until they did
+    initialised: Option<u64>,
+    search_mode: bool,
+    search_string: String,
+    last_toggle: Instant,
+    frames: Receiver<BiosEvent>,
+    // the displays shown on the Apache page
+    ah64d_changes: Vec<Receiver<BiosEvent>>,
+    // one of them changed, the frame it belongs to may come in a later tick
+    ah64d_dirty: bool,
+    // export memory as of the last finished frame
+    snapshot: Arc<MemoryImage>,
+    // the page has to be built and sent again
+    redraw: bool
+}
+
+// What the aircraft pages show, decided once per frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Page {
+    A10C2,
+    AV8B,
+    CH47F,
+    AH64D { is_cpg: bool },
+    // waiting for DCS or an aircraft without a page
+    Status
+}
+
+impl Cdu {
+    pub(crate) fn new(role: Role, device: Box<dyn Transport>, keys: Arc<KeyState>, init_packets: Vec<Vec<u8>>) -> Self {
+        Cdu {
+            role,
+            device,
+            keys,
+            gestures: GestureDetector::new(),
+            held: HeldKeys::new(),
+            display: DisplayWriter::new(),
+            init_packets,
+            initialised: None,
+            search_mode: role == Role::Reference,
+            search_string: String::new(),
+            last_toggle: Instant::now() - Duration::from_millis(TOGGLE_DELAY),
+            frames: subscribe_frames(),
+            ah64d_changes: subscribe_AH64D_page(),
+            ah64d_dirty: false,
+            snapshot: snapshot(),
+            redraw: true,
+        }
+    }
+
+    // Takes the new frame when DCS-BIOS finished one, so the page is
+    // built once per exported frame instead of on every tick. The Apache
+    // page is only built again when one of its displays changed.
+    fn update_snapshot(&mut self) {
+        let mut frame_ended = false;
+        for event in self.frames.try_iter() {
+            match event {
+                BiosEvent::FrameEnd | BiosEvent::Disconnected => frame_ended = true,
+                BiosEvent::Changed { .. } => {}
+            }
+        }
+        // drained on every tick, whatever page is shown
+        for changes in &self.ah64d_changes {
+            for event in changes.try_iter() {
+                self.ah64d_dirty |= matches!(event, BiosEvent::Changed { .. });
+            }
+        }
+        if(frame_ended){
+            let latest = snapshot();
+            if(latest.generation() != self.snapshot.generation()){
+                let before = self.current_page();
+                self.snapshot = latest;
+                let after = self.current_page();
+                if(before != after || self.ah64d_dirty || !matches!(after, Page::AH64D { .. })){
+                    self.redraw = true;
+                }
+                // redraw stays set until the page went out
+                self.ah64d_dirty = false;
+            }
+        }
+    }
+
+    fn current_page(&self) -> Page {
+        if(!is_connected()){
+            return Page::Status;
+        }
+        let module_name = get_module_name(&self.snapshot);
+        if module_name.starts_with("A-10C_2"){
+            Page::A10C2
+        }
+        else if(module_name.starts_with("AV8B")){
+            Page::AV8B
+        }
+        else if(module_name.starts_with("CH-47F")){
+            Page::CH47F
+        }
+        else if(module_name.starts_with("AH-64D_BLK_II")){
+            let is_cpg = match self.role {
+                Role::Ah64dPilot => false,
+                Role::Ah64dCpg => true,
+                _ => AH64D_isCpg(&self.snapshot),
+            };
+            Page::AH64D { is_cpg }
+        }
+        else{
+            Page::Status
+        }
+    }
+
+    fn render(&self, page: Page) -> Screen {
+        match page {
+            Page::A10C2 => get_A10C2_text(&self.snapshot),
+            Page::AV8B => get_AV8B_text(&self.snapshot),
+            Page::CH47F => get_CH47F_text(&self.snapshot),
+            Page::AH64D { is_cpg } => get_AH64D_text(&self.snapshot, is_cpg),
+            Page::Status => get_status_text(is_connected(), &get_module_name(&self.snapshot)),
+        }
+    }
+
+    // Keys are handled every tick, not only when a frame came in. Any aircraft
+    // with a file in keys/aircraft gets its keys, with or without a page.
+    // Without a page (search mode, unplugged) held keys are only let go of.
+    fn handle_keys(&mut self, page: Option<Page>, events: &[KeyEvent], gestures: &[Gesture]) {
+        let aircraft = match page {
+            Some(_) if !MANUAL_BUTTON_MAPPING && is_connected() => Some(get_module_name(&self.snapshot)),
+            _ => None,
+        };
+        let seat = match page {
+            Some(Page::AH64D { is_cpg: true }) => "CPG",
+            Some(Page::AH64D { is_cpg: false }) => "PLT",
+            _ => "",
+        };
+        handle_aircraft_input(aircraft.as_deref(), events, gestures, seat, &mut self.held);
+    }
+
+    // Uploads fonts and palette whenever the device is new, i.e. on start up and
+    // after every reconnect. The page and mode are kept, the next frame is sent in full.
+    fn ensure_initialised(&mut self) -> Result<()> {
+        if self.initialised == Some(self.device.generation()) {
+            return Ok(());
+        }
+        send_init_packets(&self.device, &self.init_packets, WRITE_DELAY_SHORT)?;
+        self.display.reset();
+        self.redraw = true;
+        // the device may have been reopened by the first write
+        self.initialised = Some(self.device.generation());
+        Ok(())
+    }
+
+    pub(crate) fn tick(&mut self) {
+        // taken first, so presses made while the unit was gone don't fire later
+        let events = self.keys.take_events();
+        let gestures = self.gestures.feed(&events, Instant::now());
+        // frames keep coming while the unit is gone, the last one is shown once it's back
+        self.update_snapshot();
+        if self.ensure_initialised().is_err() {
+            // unplugged, the transport keeps trying to reopen it. The unit lets go of all its keys.
+            self.handle_keys(None, &events, &[]);
+            return;
+        }
+
+        // msb = mode switch button, a reference unit stays on the search page.
+        // A second press right after the first is the contact bouncing.
+        for gesture in gestures.iter().filter(|g| g.is_press("MENU")) {
+            if (self.role != Role::Reference
+                && gesture.at.duration_since(self.last_toggle) >= Duration::from_millis(TOGGLE_DELAY)) {
+                    self.search_mode = !self.search_mode;
+                    self.last_toggle = gesture.at;
+                    self.redraw = true;
+            }
+        }
+        // MENU+EXEC, for a unit that shows garbage
+        if gestures.iter().any(|g| g.key == "MENU" && g.kind == GestureKind::Chord("EXEC")) {
+            self.initialised = None;
+        }
+
+        let page = self.current_page();
+        self.handle_keys(if(self.search_mode) {None} else {Some(page)}, &events, &gestures);
+
+        let screen = if(self.search_mode){
+            if(MANUAL_BUTTON_MAPPING){
+                return;
+            }
+            // typing changes it, not DCS
+            get_search_mode_disp(&gestures, &mut self.search_string)
+        }
+        else{
+            if(!self.redraw){
+                return;
+            }
+            self.render(page)
+        };
+        // a failed write means the device is gone, it gets initialised again once it's back
+        if self.display.send_text_to_disp(&self.device, WRITE_DELAY_LONG, &screen).is_ok() {
+            self.redraw = false;
+        }
+    }
+
+    pub(crate) fn run(mut self) {
+        loop {
+            self.tick();
+            thread::sleep(Duration::from_millis(THREAD_SLEEP));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controlReferenceHelper::load_control_references;
+    use crate::emulatorHelper::Emulator;
+    use crate::commandHelper::queue_command;
+    use crate::dcsBiosHelper::{read_tcp_session, set_ports, BiosConnection, BiosPorts, BIOS_TEST_LOCK, DEFAULT_PORTS};
+    use crate::keyMapHelper::{key_layout, load_key_maps};
+    use crate::mockBiosHelper::{start_mock_bios, CapturedCommand, MockBios};
+    use crate::subscriptionHelper::notifier;
+    use std::sync::MutexGuard;
+    use crate::transportHelper::MockTransport;
+    use crate::writeHelper::text_to_hex_packet;
+    use crate::{REPEAT_DELAY, REPEAT_INTERVAL};
+
+    const INIT: [u8; 3] = [0xF0, 0x00, 0x01];
+
+    fn reference_unit() -> (Cdu, Arc<MockTransport>, Arc<KeyState>) {
+        load_control_references("controls").unwrap();
+        load_key_maps("keys").unwrap();
+        let device = Arc::new(MockTransport::new());
+        let keys = Arc::new(KeyState::new(key_layout("pfp").unwrap()));
+        let cdu = Cdu::new(Role::Reference, Box::new(device.clone()), keys.clone(), vec![INIT.to_vec()]);
+        (cdu, device, keys)
+    }
+
+    // What the unit shows after the packets written so far
+    fn shown(device: &MockTransport) -> String {
+        let mut emulator = Emulator::new();
+        for packet in device.written() {
+            emulator.write(&packet).unwrap();
+        }
+        emulator.screen().cells().iter().take(24).map(|c| c.ch).collect()
+    }
+
+    #[test]
+    fn init_then_page_then_nothing_until_it_changes() {
+        let (mut cdu, device, keys) = reference_unit();
+        cdu.tick();
+        let written = device.written();
+        assert_eq!(written[0], INIT);
+        assert_eq!(written[1..], text_to_hex_packet(&get_search_mode_disp(&[], &mut String::new())));
+
+        device.clear_written();
+        cdu.tick();
+        assert!(device.written().is_empty());
+
+        keys.set_button("S", true);
+        cdu.tick();
+        assert_eq!(shown(&device).trim_end(), "SEARCH STRING: S");
+    }
+
+    #[test]
+    fn menu_exec_uploads_the_font_again() {
+        let (mut cdu, device, keys) = reference_unit();
+        cdu.tick();
+        device.clear_written();
+
+        keys.set_button("MENU", true);
+        keys.set_button("EXEC", true);
+        cdu.tick();
+        cdu.tick();
+        let written = device.written();
+        assert_eq!(written[0], INIT);
+        // the display was reset with it, the unchanged page is sent again
+        assert_eq!(written.len(), 1 + 16);
+    }
+
+    #[test]
+    fn unplugged_unit_writes_nothing() {
+        let (mut cdu, device, _keys) = reference_unit();
+        device.set_unplugged(true);
+        cdu.tick();
+        assert!(device.written().is_empty());
+        device.set_unplugged(false);
+        cdu.tick();
+        assert_eq!(device.written()[0], INIT);
+    }
+
+    #[test]
+    fn unplugged_unit_still_takes_the_frames() {
+        let (mut cdu, device, _keys) = reference_unit();
+        device.set_unplugged(true);
+        for _ in 0..3 {
+            notifier().frame_end();
+        }
+        cdu.tick();
+        // a reader of another test may have sent one since
+        assert!(cdu.frames.try_iter().count() < 3);
+    }
+
+    fn wait_for(what: &str, mut done: impl FnMut() -> bool) {
+        let start = Instant::now();
+        while !done() {
+            assert!(start.elapsed() < Duration::from_secs(5), "timed out waiting for {}", what);
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    // The bridge against a mock DCS-BIOS on a free port, for one test at a time
+    struct Session {
+        mock: Arc<MockBios>,
+        reader: Option<thread::JoinHandle<()>>,
+        _lock: MutexGuard<'static, ()>
+    }
+
+    impl Session {
+        fn start(script: &str, aircraft: &str) -> Session {
+            let lock = BIOS_TEST_LOCK.lock().unwrap_or_else(|p| p.into_inner());
+            load_control_references("controls").unwrap();
+            load_key_maps("keys").unwrap();
+            let mock = start_mock_bios(script, BiosConnection::Tcp, BiosPorts { tcp: 0, ..DEFAULT_PORTS }, false).unwrap();
+            set_ports(mock.ports());
+            let reader = read_tcp_session();
+            wait_for(aircraft, || is_connected() && get_module_name(&snapshot()).starts_with(aircraft));
+            // the command link may still wait to reconnect after an earlier session
+            wait_for("the command link", || {
+                queue_command("PING");
+                thread::sleep(Duration::from_millis(50));
+                mock.commands().iter().any(|c| c.line == "PING")
+            });
+            Session { mock, reader: Some(reader), _lock: lock }
+        }
+
+        // Commands since `from` whose control starts with prefix
+        fn commands(&self, from: usize, prefix: &str) -> Vec<CapturedCommand> {
+            self.mock.commands().into_iter().skip(from).filter(|c| c.line.starts_with(prefix)).collect()
+        }
+    }
+
+    impl Drop for Session {
+        fn drop(&mut self) {
+            self.mock.stop();
+            if let Some(reader) = self.reader.take() {
+                let _ = reader.join();
+            }
+        }
+    }
+
+    fn lines(commands: &[CapturedCommand]) -> Vec<&str> {
+        commands.iter().map(|c| c.line.as_str()).collect()
+    }
+
+    fn gap(commands: &[CapturedCommand]) -> Duration {
+        commands[1].at - commands[0].at
+    }
+
+    // A unit that shows the page of the aircraft in the mock
+    fn unit_on_page(role: Role, page: impl Fn(Page) -> bool) -> (Cdu, Arc<KeyState>) {
+        let (cdu, keys, _) = unit_on_page_with_device(role, page);
+        (cdu, keys)
+    }
+
+    fn unit_on_page_with_device(role: Role, page: impl Fn(Page) -> bool) -> (Cdu, Arc<KeyState>, Arc<MockTransport>) {
+        let keys = Arc::new(KeyState::new(key_layout("pfp").unwrap()));
+        let device = Arc::new(MockTransport::new());
+        let mut cdu = Cdu::new(role, Box::new(device.clone()), keys.clone(), vec![INIT.to_vec()]);
+        wait_for("the page", || {
+            cdu.tick();
+            page(cdu.current_page())
+        });
+        (cdu, keys, device)
+    }
+
+    #[test]
+    fn a10c_button_is_pressed_and_released() {
+        let session = Session::start("mock/a10c.txt", "A-10C_2");
+        let (mut cdu, keys) = unit_on_page(Role::Auto, |p| p == Page::A10C2);
+        let from = session.mock.commands().len();
+
+        // DEL repeats, so it is pressed and released by the bridge
+        keys.set_button("DEL", true);
+        cdu.tick();
+        keys.set_button("DEL", false);
+        cdu.tick();
+        wait_for("CDU_BCK", || session.commands(from, "CDU_BCK").len() >= 2);
+        let sent = session.commands(from, "CDU_BCK");
+        assert_eq!(lines(&sent), ["CDU_BCK TOGGLE", "CDU_BCK TOGGLE"]);
+        // held down in DCS for BUTTON_SLEEP_DELAY, not for as long as the key was
+        assert!(gap(&sent) >= Duration::from_millis(90) && gap(&sent) < Duration::from_millis(400), "{:?}", gap(&sent));
+    }
+
+    #[test]
+    fn a10c_momentary_key_follows_the_key() {
+        let session = Session::start("mock/a10c.txt", "A-10C_2");
+        let (mut cdu, keys) = unit_on_page(Role::Auto, |p| p == Page::A10C2);
+        let from = session.mock.commands().len();
+
+        keys.set_button("A", true);
+        cdu.tick();
+        thread::sleep(Duration::from_millis(300));
+        cdu.tick();
+        keys.set_button("A", false);
+        cdu.tick();
+        wait_for("CDU_A", || session.commands(from, "CDU_A ").len() >= 2);
+        let sent = session.commands(from, "CDU_A ");
+        assert_eq!(lines(&sent), ["CDU_A 1", "CDU_A 0"]);
+        assert!(gap(&sent) >= Duration::from_millis(250), "{:?}", gap(&sent));
+    }
+
+    #[test]
+    fn press_and_release_within_one_tick_leave_nothing_held() {
+        let session = Session::start("mock/a10c.txt", "A-10C_2");
+        let (mut cdu, keys) = unit_on_page(Role::Auto, |p| p == Page::A10C2);
+        let from = session.mock.commands().len();
+
+        keys.set_button("L2", true);
+        keys.set_button("L2", false);
+        cdu.tick();
+        assert!(cdu.held.is_empty());
+        wait_for("CDU_SCROLL", || session.commands(from, "CDU_SCROLL").len() >= 2);
+        assert_eq!(lines(&session.commands(from, "CDU_SCROLL")), ["CDU_SCROLL 2", "CDU_SCROLL 1"]);
+    }
+
+    #[test]
+    fn a10c_rocker_follows_the_key() {
+        let session = Session::start("mock/a10c.txt", "A-10C_2");
+        let (mut cdu, keys) = unit_on_page(Role::Auto, |p| p == Page::A10C2);
+        let from = session.mock.commands().len();
+
+        keys.set_button("L1", true);
+        cdu.tick();
+        thread::sleep(Duration::from_millis(300));
+        cdu.tick();
+        // nothing more while the key is held
+        assert_eq!(lines(&session.commands(from, "CDU_SCROLL")), ["CDU_SCROLL 0"]);
+        keys.set_button("L1", false);
+        cdu.tick();
+        wait_for("CDU_SCROLL 1", || session.commands(from, "CDU_SCROLL").len() >= 2);
+        let sent = session.commands(from, "CDU_SCROLL");
+        assert_eq!(lines(&sent), ["CDU_SCROLL 0", "CDU_SCROLL 1"]);
+        assert!(gap(&sent) >= Duration::from_millis(250), "{:?}", gap(&sent));
+    }
+
+    #[test]
+    fn ah64d_keys_go_to_the_seat_in_dcs() {
+        // the script sits in the front seat
+        let session = Session::start("mock/ah64d.txt", "AH-64D_BLK_II");
+        let (mut cdu, keys) = unit_on_page(Role::Auto, |p| p == Page::AH64D { is_cpg: true });
+        let from = session.mock.commands().len();
+
+        keys.set_button("A", true);
+        cdu.tick();
+        thread::sleep(Duration::from_millis(200));
+        keys.set_button("A", false);
+        cdu.tick();
+        wait_for("CPG_KU_A", || session.commands(from, "CPG_KU_A").len() >= 2);
+        let sent = session.commands(from, "CPG_KU_A");
+        assert_eq!(lines(&sent), ["CPG_KU_A 1", "CPG_KU_A 0"]);
+        assert!(gap(&sent) >= Duration::from_millis(150), "{:?}", gap(&sent));
+        assert!(session.commands(from, "PLT_KU").is_empty());
+    }
+
+    #[test]
+    fn ah64d_change_and_its_frame_end_in_different_ticks() {
+        let _session = Session::start("mock/ah64d.txt", "AH-64D_BLK_II");
+        let (mut cdu, _keys, device) = unit_on_page_with_device(Role::Auto, |p| matches!(p, Page::AH64D { .. }));
+        // the events of the mock come in through these from now on
+        let (frames, frame_events) = std::sync::mpsc::channel();
+        let (changes, change_events) = std::sync::mpsc::channel();
+        cdu.frames = frame_events;
+        cdu.ah64d_changes = vec![change_events];
+        // a redraw sends the whole page, so it shows in the writes
+        cdu.display.reset();
+        device.clear_written();
+
+        changes.send(BiosEvent::Changed { address: 0, value: [0, 0] }).unwrap();
+        cdu.tick();
+        assert!(device.written().is_empty());
+
+        // the mock published a newer frame by now
+        thread::sleep(Duration::from_millis(100));
+        frames.send(BiosEvent::FrameEnd).unwrap();
+        cdu.tick();
+        assert_eq!(device.written().len(), 16);
+
+        // nothing changed in the next frame
+        device.clear_written();
+        thread::sleep(Duration::from_millis(100));
+        frames.send(BiosEvent::FrameEnd).unwrap();
+        cdu.tick();
+        assert!(device.written().is_empty());
+    }
+
+    #[test]
+    fn a10c_page_rocker_repeats_while_held() {
+        let session = Session::start("mock/a10c.txt", "A-10C_2");
+        let (mut cdu, keys) = unit_on_page(Role::Auto, |p| p == Page::A10C2);
+        let from = session.mock.commands().len();
+
+        keys.set_button("NEXT PAGE", true);
+        let start = Instant::now();
+        // down, then two repeats after REPEAT_DELAY and REPEAT_INTERVAL
+        while start.elapsed() < REPEAT_DELAY + REPEAT_INTERVAL + Duration::from_millis(50) {
+            cdu.tick();
+            thread::sleep(Duration::from_millis(THREAD_SLEEP));
+        }
+        keys.set_button("NEXT PAGE", false);
+        cdu.tick();
+        wait_for("CDU_PG", || session.commands(from, "CDU_PG").len() >= 6);
+        thread::sleep(Duration::from_millis(300));
+        let sent = session.commands(from, "CDU_PG");
+        assert_eq!(lines(&sent), ["CDU_PG 0", "CDU_PG 1", "CDU_PG 0", "CDU_PG 1", "CDU_PG 0", "CDU_PG 1"]);
+        // the rocker is let go before the next repeat
+        assert!(gap(&sent) >= Duration::from_millis(90) && gap(&sent) < REPEAT_INTERVAL, "{:?}", gap(&sent));
+        assert!(cdu.held.is_empty());
+    }
+
+    #[test]
+    fn menu_exec_sends_no_ku_exec() {
+        let session = Session::start("mock/ah64d.txt", "AH-64D_BLK_II");
+        let (mut cdu, keys) = unit_on_page(Role::Auto, |p| p == Page::AH64D { is_cpg: true });
+        let from = session.mock.commands().len();
+
+        keys.set_button("MENU", true);
+        keys.set_button("EXEC", true);
+        cdu.tick();
+        keys.set_button("EXEC", false);
+        keys.set_button("MENU", false);
+        cdu.tick();
+        // EXEC on its own still goes to the KU
+        keys.set_button("EXEC", true);
+        cdu.tick();
+        keys.set_button("EXEC", false);
+        cdu.tick();
+        wait_for("CPG_KU_EXEC", || session.commands(from, "CPG_KU_EXEC").len() >= 2);
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(lines(&session.commands(from, "CPG_KU_EXEC")), ["CPG_KU_EXEC 1", "CPG_KU_EXEC 0"]);
+        assert!(session.commands(from, "PLT_KU_EXEC").is_empty());
+    }
+}
@@ -0,0 +1,259 @@
+#![allow(non_snake_case)]
+// Commands to DCS-BIOS go through one sender thread with a long lived
+// connection. Callers only queue them (now or after a delay), so the render
+// and input loops never wait for the network. Commands that can't be sent are
+// kept in order and go out once the connection is back, so no key stays held.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, TcpStream, UdpSocket};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::dcsBiosHelper::{connection, ports, BiosConnection, DCS_HOST};
+
+// wakes up at least this often to keep the TCP connection drained
+const IDLE_WAKE: Duration = Duration::from_millis(100);
+const RECONNECT_DELAY: Duration = Duration::from_millis(1000);
+// keys only make commands while the export is up, so few pile up
+const MAX_UNSENT: usize = 256;
+
+struct Command {
+    due: Instant,
+    message: String
+}
+
+static QUEUE: OnceLock<Sender<Command>> = OnceLock::new();
+
+// Starts the sender thread on first use
+fn queue() -> &'static Sender<Command> {
+    QUEUE.get_or_init(|| {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || run_sender(rx));
+        tx
+    })
+}
+
+pub fn queue_command(message: &str) {
+    queue_command_in(message, Duration::ZERO);
+}
+
+pub fn queue_command_in(message: &str, delay: Duration) {
+    let _ = queue().send(Command { due: Instant::now() + delay, message: message.to_string() });
+}
+
+// Press and release of one button, a second press of the same button is
+// ignored until the release of the first went out
+pub fn queue_press(press: &str, release: &str, hold: Duration) {
+    static IN_FLIGHT: OnceLock<Mutex<HashMap<String, Instant>>> = OnceLock::new();
+    let mut in_flight = IN_FLIGHT.get_or_init(|| Mutex::new(HashMap::new())).lock().unwrap();
+
+    let now = Instant::now();
+    if in_flight.get(press).is_some_and(|release_at| *release_at > now) {
+        return;
+    }
+    in_flight.insert(press.to_string(), now + hold);
+    queue_command(press);
+    queue_command_in(release, hold);
+}
+
+enum Link {
+    Tcp(Option<TcpStream>),
+    Udp(Option<UdpSocket>)
+}
+
+struct CommandLink {
+    link: Link,
+    last_attempt: Option<Instant>
+}
+
+impl CommandLink {
+    fn new() -> Self {
+        let link = match connection() {
+            BiosConnection::Tcp => Link::Tcp(None),
+            BiosConnection::Udp => Link::Udp(None),
+        };
+        CommandLink { link, last_attempt: None }
+    }
+
+    fn is_open(&self) -> bool {
+        match &self.link {
+            Link::Tcp(stream) => stream.is_some(),
+            Link::Udp(socket) => socket.is_some(),
+        }
+    }
+
+    fn open(&mut self) -> io::Result<()> {
+        match &mut self.link {
+            Link::Tcp(stream) => {
+                let s = TcpStream::connect((DCS_HOST, ports().tcp))?;
+                s.set_nodelay(true)?;
+                s.set_nonblocking(true)?;
+                *stream = Some(s);
+            }
+            Link::Udp(socket) => {
+                *socket = Some(UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?);
+            }
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        match &mut self.link {
+            Link::Tcp(stream) => *stream = None,
+            Link::Udp(socket) => *socket = None,
+        }
+    }
+
+    fn send(&mut self, message: &str) -> io::Result<()> {
+        if !self.is_open() {
+            // don't hammer a DCS that isn't running
+            if self.last_attempt.is_some_and(|t| t.elapsed() < RECONNECT_DELAY) {
+                return Err(io::Error::new(ErrorKind::NotConnected, "waiting to reconnect"));
+            }
+            self.last_attempt = Some(Instant::now());
+            self.open()?;
+        }
+
+        let line = format!("{}\n", message);
+        let res = match &mut self.link {
+            Link::Tcp(Some(stream)) => write_all_nonblocking(stream, line.as_bytes()),
+            Link::Udp(Some(socket)) => socket.send_to(line.as_bytes(), (DCS_HOST, ports().command)).map(|_| ()),
+            _ => Ok(()),
+        };
+        if res.is_err() {
+            self.close();
+        }
+        res
+    }
+
+    // DCS-BIOS sends the export stream to every TCP client, this one doesn't
+    // need it but has to read it so the server doesn't stall on a full buffer
+    fn drain(&mut self) {
+        let mut closed = false;
+        if let Link::Tcp(Some(stream)) = &mut self.link {
+            let mut buf = [0u8; 4096];
+            loop {
+                match stream.read(&mut buf) {
+                    Ok(0) => {
+                        closed = true;
+                        break;
+                    }
+                    Ok(_) => continue,
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                    Err(_) => {
+                        closed = true;
+                        break;
+                    }
+                }
+            }
+        }
+        if closed {
+            self.close();
+        }
+    }
+}
+
+fn write_all_nonblocking(stream: &mut TcpStream, mut data: &[u8]) -> io::Result<()> {
+    while !data.is_empty() {
+        match stream.write(data) {
+            Ok(0) => return Err(io::Error::new(ErrorKind::WriteZero, "connection closed")),
+            Ok(n) => data = &data[n..],
+            Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(1)),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+fn run_sender(rx: Receiver<Command>) {
+    // earliest due first, seq keeps commands with the same due time in order
+    let mut pending: BinaryHeap<Reverse<(Instant, u64, String)>> = BinaryHeap::new();
+    let mut seq: u64 = 0;
+    // due, waiting for the connection, oldest first
+    let mut unsent: VecDeque<String> = VecDeque::new();
+    let mut reported = false;
+    let mut link = CommandLink::new();
+
+    loop {
+        let now = Instant::now();
+        let wait = pending
+            .peek()
+            .map(|Reverse((due, _, _))| due.saturating_duration_since(now))
+            .unwrap_or(IDLE_WAKE)
+            .min(IDLE_WAKE);
+
+        match rx.recv_timeout(wait) {
+            Ok(command) => {
+                pending.push(Reverse((command.due, seq, command.message)));
+                seq += 1;
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+
+        let now = Instant::now();
+        while pending.peek().is_some_and(|Reverse((due, _, _))| *due <= now) {
+            let Reverse((_, _, message)) = pending.pop().unwrap();
+            if unsent.len() == MAX_UNSENT {
+                eprintln!("DCS-BIOS command '{}' dropped, too many unsent", unsent.pop_front().unwrap());
+            }
+            unsent.push_back(message);
+        }
+        while let Some(message) = unsent.front() {
+            match link.send(message) {
+                Ok(()) => {
+                    unsent.pop_front();
+                    reported = false;
+                }
+                Err(e) => {
+                    // once per outage, the link retries every RECONNECT_DELAY
+                    if !reported && e.kind() != ErrorKind::NotConnected {
+                        eprintln!("DCS-BIOS command '{}' not sent: {}, retrying", message, e);
+                        reported = true;
+                    }
+                    break;
+                }
+            }
+        }
+        link.drain();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dcsBiosHelper::{set_ports, BiosPorts, BIOS_TEST_LOCK, DEFAULT_PORTS};
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+
+    #[test]
+    fn commands_wait_for_the_connection() {
+        let _lock = BIOS_TEST_LOCK.lock().unwrap_or_else(|p| p.into_inner());
+        // a free port with nothing listening yet
+        let port = TcpListener::bind((DCS_HOST, 0)).unwrap().local_addr().unwrap().port();
+        set_ports(BiosPorts { tcp: port, ..DEFAULT_PORTS });
+        let (tx, rx) = mpsc::channel();
+        let sender = thread::spawn(move || run_sender(rx));
+
+        let now = Instant::now();
+        for message in ["CDU_A 1", "CDU_A 0"] {
+            tx.send(Command { due: now, message: message.to_string() }).unwrap();
+        }
+        thread::sleep(IDLE_WAKE * 2);
+
+        // DCS-BIOS comes up, the press and the release follow in order
+        let listener = TcpListener::bind((DCS_HOST, port)).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        stream.set_read_timeout(Some(RECONNECT_DELAY * 3)).unwrap();
+        let lines: Vec<String> = BufReader::new(stream).lines().take(2).map(Result::unwrap).collect();
+        assert_eq!(lines, ["CDU_A 1", "CDU_A 0"]);
+
+        drop(tx);
+        sender.join().unwrap();
+        set_ports(DEFAULT_PORTS);
+    }
+}
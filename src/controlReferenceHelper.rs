@@ -0,0 +1,313 @@
+#![allow(non_snake_case)]
+// DCS-BIOS control reference, the JSON files DCS-BIOS writes for every module
+// (Saved Games/DCS/Scripts/DCS-BIOS/doc/json). They tell where a control lives
+// in the export memory, so the pages keep working when DCS-BIOS moves things.
+// The file name is the module name, e.g. controls/AV8BNA.json is "AV8BNA".
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+
+#[derive(Deserialize)]
+struct JsonInput {
+    interface: String,
+    #[serde(default)]
+    max_value: Option<u32>,
+    #[serde(default)]
+    argument: Option<String>
+}
+
+#[derive(Deserialize)]
+struct JsonOutput {
+    address: u16,
+    #[serde(default)]
+    mask: Option<u16>,
+    #[serde(default)]
+    shift_by: Option<u8>,
+    #[serde(default)]
+    max_length: Option<u16>
+}
+
+#[derive(Deserialize)]
+struct JsonControl {
+    identifier: String,
+    #[serde(default)]
+    inputs: Vec<JsonInput>,
+    #[serde(default)]
+    outputs: Vec<JsonOutput>
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Output {
+    pub(crate) address: u16,
+    pub(crate) mask: u16,
+    pub(crate) shift_by: u8,
+    // bytes, only for string outputs
+    pub(crate) max_length: u16
+}
+
+// How a control can be operated, the command is "<IDENTIFIER> <argument>"
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum InputInterface {
+    // "TOGGLE" and similar fixed arguments
+    Action(String),
+    // DEC / INC
+    FixedStep,
+    // 0..=max_value
+    SetState(u32)
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Control {
+    pub(crate) identifier: String,
+    pub(crate) inputs: Vec<InputInterface>,
+    pub(crate) outputs: Vec<Output>
+}
+
+// What output() hands out for controls that aren't in the reference,
+// reads as 0 / empty string
+static MISSING: Output = Output { address: 0xffff, mask: 0, shift_by: 0, max_length: 0 };
+
+static REFERENCES: OnceLock<HashMap<String, HashMap<String, Control>>> = OnceLock::new();
+
+fn convert(control: JsonControl) -> Control {
+    let inputs = control
+        .inputs
+        .into_iter()
+        .filter_map(|i| match i.interface.as_str() {
+            "action" => Some(InputInterface::Action(i.argument.unwrap_or_else(|| "TOGGLE".to_string()))),
+            "fixed_step" => Some(InputInterface::FixedStep),
+            "set_state" => Some(InputInterface::SetState(i.max_value.unwrap_or(1))),
+            // variable_step and the rest aren't sent by any page
+            _ => None,
+        })
+        .collect();
+    let outputs = control
+        .outputs
+        .into_iter()
+        .map(|o| Output {
+            address: o.address,
+            mask: o.mask.unwrap_or(0xffff),
+            shift_by: o.shift_by.unwrap_or(0),
+            max_length: o.max_length.unwrap_or(0),
+        })
+        .collect();
+    Control { identifier: control.identifier, inputs, outputs }
+}
+
+// One module file: { "<category>": { "<identifier>": { ... } } }
+pub(crate) fn parse_control_reference(json: &str) -> Result<HashMap<String, Control>> {
+    let categories: HashMap<String, HashMap<String, JsonControl>> = serde_json::from_str(json)?;
+    let mut controls = HashMap::new();
+    for (_, category) in categories {
+        for (_, control) in category {
+            let control = convert(control);
+            controls.insert(control.identifier.clone(), control);
+        }
+    }
+    Ok(controls)
+}
+
+// Loads every *.json in dir, returns the module names. Only the first call has an effect.
+pub(crate) fn load_control_references(dir: &str) -> Result<Vec<String>> {
+    let mut modules = HashMap::new();
+    let entries = fs::read_dir(dir).with_context(|| format!("Failed to open control reference directory {}", dir))?;
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("json") {
+            continue;
+        }
+        let module = module_name(&path);
+        let json = fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let controls = parse_control_reference(&json).with_context(|| format!("Invalid control reference {}", path.display()))?;
+        modules.insert(module, controls);
+    }
+    let mut names: Vec<String> = modules.keys().cloned().collect();
+    names.sort();
+    let _ = REFERENCES.set(modules);
+    Ok(names)
+}
+
+fn module_name(path: &Path) -> String {
+    path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+pub(crate) fn control(module: &str, identifier: &str) -> Option<&'static Control> {
+    REFERENCES.get()?.get(module)?.get(identifier)
+}
+
+// All controls of a module, in no particular order
+pub(crate) fn controls(module: &str) -> impl Iterator<Item = &'static Control> {
+    REFERENCES.get().and_then(|r| r.get(module)).into_iter().flat_map(|m| m.values())
+}
+
+// Every missing control is reported once, not on every frame
+fn report_missing(module: &str, identifier: &str, what: &str) {
+    static REPORTED: OnceLock<Mutex<HashSet<String>>> = OnceLock::new();
+    let key = format!("{}/{}/{}", module, identifier, what);
+    if REPORTED.get_or_init(|| Mutex::new(HashSet::new())).lock().unwrap().insert(key) {
+        eprintln!("PFP_WRITER: {} has no {} {} in the control reference", module, what, identifier);
+    }
+}
+
+// First output of a control. Unknown controls read as 0,
+// a page with one missing value is better than no page.
+pub(crate) fn output(module: &str, identifier: &str) -> &'static Output {
+    match control(module, identifier).and_then(|c| c.outputs.first()) {
+        Some(output) => output,
+        None => {
+            report_missing(module, identifier, "output");
+            &MISSING
+        }
+    }
+}
+
+// Command for a push button, "CDU_LSK_3L TOGGLE"
+pub(crate) fn action_command(module: &str, identifier: &str) -> String {
+    let argument = control(module, identifier).and_then(|c| {
+        c.inputs.iter().find_map(|i| match i {
+            InputInterface::Action(argument) => Some(argument.as_str()),
+            _ => None,
+        })
+    });
+    match argument {
+        Some(argument) => format!("{} {}", identifier, argument),
+        None => {
+            // DCS-BIOS still knows it if only the reference is outdated
+            report_missing(module, identifier, "action input");
+            format!("{} TOGGLE", identifier)
+        }
+    }
+}
+
+// Command that moves a switch or rocker to a position, "CDU_SCROLL 2"
+pub(crate) fn state_command(module: &str, identifier: &str, state: u32) -> String {
+    let max_value = control(module, identifier).and_then(|c| {
+        c.inputs.iter().find_map(|i| match i {
+            InputInterface::SetState(max_value) => Some(*max_value),
+            _ => None,
+        })
+    });
+    match max_value {
+        Some(max_value) if state > max_value => {
+            report_missing(module, identifier, &format!("position {}", state));
+            format!("{} {}", identifier, max_value)
+        }
+        Some(_) => format!("{} {}", identifier, state),
+        None => {
+            report_missing(module, identifier, "set_state input");
+            format!("{} {}", identifier, state)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = r#"{
+        "CDU": {
+            "CDU_LINE0": { "identifier": "CDU_LINE0", "category": "CDU",
+                "outputs": [ { "address": 4544, "max_length": 24, "type": "string" } ] },
+            "CDU_SCROLL": { "identifier": "CDU_SCROLL", "category": "CDU",
+                "inputs": [ { "interface": "fixed_step" }, { "interface": "set_state", "max_value": 2 },
+                            { "interface": "variable_step", "max_value": 65535 } ] }
+        },
+        "Switches": {
+            "H2O_MODE": { "identifier": "H2O_MODE", "category": "Switches",
+                "inputs": [ { "interface": "action", "argument": "INC" } ],
+                "outputs": [ { "address": 30780, "mask": 12, "shift_by": 2, "type": "integer" } ] }
+        }
+    }"#;
+
+    fn load() {
+        load_control_references("controls").unwrap();
+    }
+
+    fn first_output(module: &str, identifier: &str) -> &'static Output {
+        control(module, identifier).and_then(|c| c.outputs.first()).unwrap_or_else(|| panic!("{} {} missing", module, identifier))
+    }
+
+    fn assert_integer(module: &str, identifier: &str, address: u16, mask: u16, shift_by: u8) {
+        assert_eq!(first_output(module, identifier), &Output { address, mask, shift_by, max_length: 0 }, "{}", identifier);
+    }
+
+    fn assert_string(module: &str, identifier: &str, address: u16, max_length: u16) {
+        let output = first_output(module, identifier);
+        assert_eq!((output.address, output.max_length), (address, max_length), "{}", identifier);
+    }
+
+    #[test]
+    fn parses_sample_reference() {
+        let controls = parse_control_reference(SAMPLE).unwrap();
+        assert_eq!(controls["CDU_LINE0"].outputs, [Output { address: 0x11c0, mask: 0xffff, shift_by: 0, max_length: 24 }]);
+        // variable_step isn't used by any page
+        assert_eq!(controls["CDU_SCROLL"].inputs, [InputInterface::FixedStep, InputInterface::SetState(2)]);
+        assert_eq!(controls["H2O_MODE"].inputs, [InputInterface::Action("INC".to_string())]);
+        assert_eq!(controls["H2O_MODE"].outputs, [Output { address: 0x783c, mask: 0x000c, shift_by: 2, max_length: 0 }]);
+    }
+
+    // The addresses the pages read before they went through the reference
+    #[test]
+    fn reference_matches_the_known_addresses() {
+        load();
+        assert_string("MetadataStart", "_ACFT_NAME", 0x0000, 24);
+        for i in 0..10 {
+            assert_string("A-10C", &format!("CDU_LINE{}", i), 0x11c0 + 0x18 * i, 24);
+        }
+        for i in 0..4 {
+            assert_string("AH-64D", &format!("PLT_EUFD_LINE{}", i + 1), 0x80c2 + 56 * i, 56);
+        }
+        assert_string("AH-64D", "PLT_KU_DISPLAY", 0x808e, 22);
+        assert_string("AH-64D", "CPG_KU_DISPLAY", 0x80ac, 22);
+        assert_integer("AH-64D", "SEAT_POSITION", 0x8750, 0x0100, 8);
+        for i in 0..14 {
+            assert_string("CH-47F", &format!("CPLT_CDU_LINE{}", i + 1), 0x9e4e + 0x18 * i, 24);
+        }
+
+        assert_integer("AV8BNA", "H2O_MODE", 0x783c, 0x000c, 2);
+        assert_integer("AV8BNA", "FLAP_MODE", 0x783a, 0x0180, 7);
+        assert_integer("AV8BNA", "GEAR_LEVER", 0x783a, 0x8000, 15);
+        assert_integer("AV8BNA", "MASTER_ARM", 0x7836, 0x4000, 14);
+        assert_integer("AV8BNA", "MC_AG", 0x7880, 0x0800, 11);
+        assert_integer("AV8BNA", "MC_NAV", 0x7880, 0x0200, 9);
+        assert_integer("AV8BNA", "MC_VSTOL", 0x7880, 0x0400, 10);
+        assert_integer("AV8BNA", "SPEED_BRAKE_POS", 0x794e, 0xffff, 0);
+        assert_integer("AV8BNA", "EDP_NOZZLE", 0x78ae, 0xffff, 0);
+        for (i, address) in [0x78aa, 0x78ac].into_iter().enumerate() {
+            assert_integer("AV8BNA", &format!("EDP_H2O_{}", i + 1), address, 0xffff, 0);
+        }
+        for (i, address) in [0x7890, 0x7892, 0x7894, 0x7896].into_iter().enumerate() {
+            assert_integer("AV8BNA", &format!("EDP_RPM_{}", i + 1), address, 0xffff, 0);
+        }
+        for (i, address) in [0x78b2, 0x78b4, 0x78b6, 0x78b8, 0x78ba].into_iter().enumerate() {
+            assert_integer("AV8BNA", &format!("FUEL_TOTAL_{}", i + 1), address, 0xffff, 0);
+        }
+    }
+
+    #[test]
+    fn missing_control_reads_as_nothing() {
+        load();
+        assert_eq!(output("A-10C", "NO_SUCH_LINE"), &MISSING);
+        assert_eq!(output("NoSuchModule", "CDU_LINE0"), &MISSING);
+        assert_eq!((MISSING.address, MISSING.mask, MISSING.max_length), (0xffff, 0, 0));
+    }
+
+    #[test]
+    fn command_formatting() {
+        load();
+        assert_eq!(action_command("A-10C", "CDU_A"), "CDU_A TOGGLE");
+        assert_eq!(action_command("AH-64D", "CPG_KU_CLR"), "CPG_KU_CLR TOGGLE");
+        // still sent, DCS-BIOS may know it
+        assert_eq!(action_command("A-10C", "CDU_NO_SUCH_KEY"), "CDU_NO_SUCH_KEY TOGGLE");
+
+        assert_eq!(state_command("A-10C", "CDU_SCROLL", 0), "CDU_SCROLL 0");
+        assert_eq!(state_command("A-10C", "CDU_SCROLL", 2), "CDU_SCROLL 2");
+        // above max_value, the furthest position is sent
+        assert_eq!(state_command("A-10C", "CDU_SCROLL", 3), "CDU_SCROLL 2");
+        assert_eq!(state_command("A-10C", "CDU_NO_SUCH_ROCKER", 1), "CDU_NO_SUCH_ROCKER 1");
+    }
+}
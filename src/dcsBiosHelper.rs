@@ -2,114 +2,342 @@
 #![allow(unused_parens)]
 
 
-use std::io::{ErrorKind, Read, Result, Write};
-use std::net::TcpStream;
-use std::time::Duration;
-use once_cell::sync::Lazy;
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
-use hex;
+use std::io::{ErrorKind, Read};
+use std::net::{Ipv4Addr, SocketAddr, TcpStream, UdpSocket};
+use std::time::{Duration, Instant};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{OnceLock, RwLock};
 use std::io::{self};
 use std::thread::sleep;
 
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::commandHelper::{queue_command, queue_command_in, queue_press};
+use crate::exportReplayHelper::ExportRecorder;
+use crate::memoryImageHelper::MemoryWriter;
+use crate::streamParserHelper::ExportParser;
+use crate::subscriptionHelper::{notifier, UPDATE_COUNTER_ADDRESS};
+
 
-pub type SharedMap = Arc<Mutex<HashMap<u16, [u8; 2]>>>;
 const BUTTON_SLEEP_DELAY: u64 = 100;
 const STREAM_TIMEOUT: u64 = 500;
+// no export data for this long counts as disconnected
+const STALE_TIMEOUT: Duration = Duration::from_secs(5);
+const BACKOFF_MIN: Duration = Duration::from_millis(500);
+const BACKOFF_MAX: Duration = Duration::from_secs(10);
+// DCS-BIOS runs on this machine, change for a DCS PC on the LAN
+pub(crate) const DCS_HOST: Ipv4Addr = Ipv4Addr::LOCALHOST;
+pub(crate) const TCP_PORT: u16 = 7778;
+pub(crate) const MULTICAST_GROUP: Ipv4Addr = Ipv4Addr::new(239, 255, 50, 10);
+pub(crate) const EXPORT_PORT: u16 = 5010;
+pub(crate) const COMMAND_PORT: u16 = 7778;
+
+// Where the bridge finds DCS-BIOS on DCS_HOST, a local server started on
+// ports of its own (0 picks a free one) hands its ports to set_ports
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct BiosPorts {
+    pub(crate) tcp: u16,
+    // multicast export, used as given
+    pub(crate) export: u16,
+    pub(crate) command: u16
+}
+
+pub(crate) const DEFAULT_PORTS: BiosPorts = BiosPorts { tcp: TCP_PORT, export: EXPORT_PORT, command: COMMAND_PORT };
+
+static PORTS: RwLock<BiosPorts> = RwLock::new(DEFAULT_PORTS);
+
+// Takes effect on the next (re)connect
+pub(crate) fn set_ports(new_ports: BiosPorts) {
+    *PORTS.write().unwrap_or_else(|p| p.into_inner()) = new_ports;
+}
+
+pub(crate) fn ports() -> BiosPorts {
+    *PORTS.read().unwrap_or_else(|p| p.into_inner())
+}
+
+// Tcp: export from a TCP connection to DCS-BIOS, commands over a second one the
+//      command sender keeps open. DCS-BIOS serves any number of TCP clients.
+// Udp: export from the multicast group and commands as datagrams, the standard
+//      DCS-BIOS setup that other consumers on the machine or LAN can share.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BiosConnection {
+    Tcp,
+    Udp
+}
 
-/// Public, static, thread-safe hash map (u8 → u8)
-pub static GLOBAL_MAP: Lazy<SharedMap> = Lazy::new(|| {
-    Arc::new(Mutex::new(HashMap::new()))
-});
+static CONNECTION: OnceLock<BiosConnection> = OnceLock::new();
+static RECORDER: OnceLock<ExportRecorder> = OnceLock::new();
 
-/// Public getter (returns an Arc clone)
-pub fn get_map() -> SharedMap {
-    GLOBAL_MAP.clone()
+// Call before read_stream, later calls are ignored
+pub fn set_connection(connection: BiosConnection) {
+    let _ = CONNECTION.set(connection);
 }
 
-fn update_vals(data: &[u8]) {
-    if data.len() < 8 { return; }
-    let mut pos = 4; // skip 8-byte stream header
+pub(crate) fn connection() -> BiosConnection {
+    CONNECTION.get().copied().unwrap_or(BiosConnection::Tcp)
+}
 
-    let map = get_map();
-    let mut m = map.lock().unwrap();
+// Every read from DCS-BIOS goes to the recording as well, call before read_stream
+pub(crate) fn set_export_recorder(recorder: ExportRecorder) {
+    let _ = RECORDER.set(recorder);
+}
 
-    while pos + 4 <= data.len() {
-        // Header for this block
-        let start = u16::from_le_bytes([data[pos],     data[pos + 1]]);
-        let len   = u16::from_le_bytes([data[pos + 2], data[pos + 3]]); // FIXED
-        pos += 4;
+static CONNECTED: AtomicBool = AtomicBool::new(false);
 
-        let byte_len = len as usize;
-        if pos + byte_len > data.len() {
-            // Caller must supply remainder in a subsequent call (see B)
-            break;
+// True while export data keeps coming in, pages show "WAITING FOR DCS" otherwise
+pub fn is_connected() -> bool {
+    CONNECTED.load(Ordering::SeqCst)
+}
+
+fn mark_connected() {
+    if !CONNECTED.swap(true, Ordering::SeqCst) {
+        println!("DCS-BIOS: connected");
+    }
+}
+
+// The data of the last mission must not stay on the displays
+fn mark_disconnected(memory: &mut MemoryWriter) {
+    if CONNECTED.swap(false, Ordering::SeqCst) {
+        println!("DCS-BIOS: waiting for DCS");
+    }
+    memory.clear();
+    memory.publish();
+    notifier().disconnected();
+}
+
+fn update_vals(parser: &mut ExportParser, memory: &mut MemoryWriter, data: &[u8]) {
+    if let Some(recorder) = RECORDER.get() {
+        recorder.record(data);
+    }
+    let mut notifier = notifier();
+    // blocks split across reads are finished by the next call
+    parser.feed(data, &mut |addr, word| {
+        if memory.write(addr, word) {
+            notifier.changed(addr, word);
         }
+        // the counter comes last, the frame is complete
+        if addr == UPDATE_COUNTER_ADDRESS {
+            memory.publish();
+            notifier.frame_end();
+        }
+    });
+    drop(notifier);
+    mark_connected();
+}
+
+// Keeps the export stream running for the lifetime of the bridge. Whenever it
+// ends (DCS restarted, mission quit, DCS-BIOS not running yet) the state is
+// cleared and it is opened again, waiting a bit longer after every failure.
+pub fn read_stream() {
+    let mut backoff = BACKOFF_MIN;
+    let mut last_error = String::new();
+    let mut memory = MemoryWriter::new();
+    loop {
+        let started = Instant::now();
+        let res = match connection() {
+            BiosConnection::Tcp => read_tcp_stream(&mut memory),
+            BiosConnection::Udp => read_udp_stream(&mut memory, &AtomicBool::new(false)),
+        };
+        mark_disconnected(&mut memory);
 
-        // DCS-BIOS payload is a sequence of 2-byte words; addresses advance by 2
-        let mut addr = start;
-        let payload = &data[pos .. pos + byte_len];
-        for w in payload.chunks_exact(2) {
-            m.insert(addr, [w[0], w[1]]);
-            addr = addr.wrapping_add(2);
+        // a session that ran for a while starts over with the short delay
+        if started.elapsed() >= STALE_TIMEOUT {
+            backoff = BACKOFF_MIN;
         }
-        pos += byte_len;
+        let error = match res {
+            Ok(()) => String::from("connection closed by DCS"),
+            Err(e) => e.to_string(),
+        };
+        // the same error on every retry is just noise
+        if error != last_error {
+            eprintln!("DCS-BIOS: {}, retrying", error);
+            last_error = error;
+        }
+        sleep(backoff);
+        backoff = (backoff * 2).min(BACKOFF_MAX);
     }
 }
 
-pub fn read_stream() -> io::Result<()> {
-    let mut stream = TcpStream::connect("127.0.0.1:7778")?;
-    // Block up to 200 ms per read; if no data arrives, return TimedOut.
+fn is_timeout(e: &io::Error) -> bool {
+    e.kind() == ErrorKind::TimedOut || e.kind() == ErrorKind::WouldBlock
+}
+
+fn read_tcp_stream(memory: &mut MemoryWriter) -> io::Result<()> {
+    let mut stream = TcpStream::connect((DCS_HOST, ports().tcp))?;
+    // Block up to STREAM_TIMEOUT per read, so a silent stream is noticed
     stream.set_read_timeout(Some(Duration::from_millis(STREAM_TIMEOUT)))?;
 
     let mut buffer = [0u8; 4096];
-    let mut is_first_time = true;
+    let mut parser = ExportParser::new();
+    let mut last_data = Instant::now();
 
     loop {
         match stream.read(&mut buffer) {
-            Ok(0) => {
-                eprintln!("Connection closed by peer");
-                return Ok(());
-            }
+            Ok(0) => return Ok(()),
             Ok(n) => {
-                // Process the received bytes
-                if (is_first_time){
-                    println!("\nConnection OK");
-                    is_first_time = false;
-                }
-                update_vals(&buffer[..n]);
+                last_data = Instant::now();
+                update_vals(&mut parser, memory, &buffer[..n]);
             }
-            Err(e) if e.kind() == ErrorKind::TimedOut => {
-                // No data within the timeout — avoid a tight spin loop.
-                sleep(Duration::from_millis(5));
-                continue;
+            // DCS-BIOS exports continuously while a mission runs, a silent
+            // connection belongs to a hung or closed DCS
+            Err(e) if is_timeout(&e) => {
+                if last_data.elapsed() >= STALE_TIMEOUT {
+                    return Err(io::Error::new(ErrorKind::TimedOut, "no data from DCS"));
+                }
             }
-            Err(e) => {
-                eprintln!("Connection closed");
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn open_multicast_socket(port: u16) -> io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    // other DCS-BIOS consumers listen on the same port
+    socket.set_reuse_address(true)?;
+    socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)).into())?;
+    socket.join_multicast_v4(&MULTICAST_GROUP, &Ipv4Addr::UNSPECIFIED)?;
+    let socket: UdpSocket = socket.into();
+    socket.set_read_timeout(Some(Duration::from_millis(STREAM_TIMEOUT)))?;
+    Ok(socket)
+}
 
-                // Propagate unexpected I/O errors to the caller.
-                return Err(e);
+// The socket stays open while DCS comes and goes, only the state changes.
+// Returns once stop is set, checked at least every STREAM_TIMEOUT.
+fn read_udp_stream(memory: &mut MemoryWriter, stop: &AtomicBool) -> io::Result<()> {
+    let port = ports().export;
+    let socket = open_multicast_socket(port)?;
+    println!("DCS-BIOS: listening on {}:{}", MULTICAST_GROUP, port);
+
+    let mut buffer = [0u8; 4096];
+    let mut parser = ExportParser::new();
+    let mut last_data = Instant::now();
+
+    while !stop.load(Ordering::SeqCst) {
+        match socket.recv(&mut buffer) {
+            Ok(n) => {
+                last_data = Instant::now();
+                update_vals(&mut parser, memory, &buffer[..n]);
+            }
+            Err(e) if is_timeout(&e) => {
+                if is_connected() && last_data.elapsed() >= STALE_TIMEOUT {
+                    mark_disconnected(memory);
+                    // the next datagram starts with a sync anyway
+                    parser = ExportParser::new();
+                }
             }
+            Err(e) => return Err(e),
         }
     }
+    Ok(())
 }
 
 pub fn send_button_press(button: &str){
-    let _ = send_message_to_dcsbios(&button);
-    sleep(Duration::from_millis(BUTTON_SLEEP_DELAY));
-    let _ = send_message_to_dcsbios(&button);
+    queue_press(button, button, Duration::from_millis(BUTTON_SLEEP_DELAY));
 }
 
-pub fn send_button_state_press(state_1: &str, state_2: &str){
-    let _ = send_message_to_dcsbios(&state_1);
-    sleep(Duration::from_millis(BUTTON_SLEEP_DELAY));
-    let _ = send_message_to_dcsbios(&state_2);
+// Presses one after the other, e.g. CLR twice to clear an error message and then the scratchpad
+pub fn send_button_presses(button: &str, times: u32){
+    let hold = Duration::from_millis(BUTTON_SLEEP_DELAY);
+    for i in 0..times {
+        queue_command_in(button, hold * (2 * i));
+        queue_command_in(button, hold * (2 * i + 1));
+    }
 }
 
-pub fn send_message_to_dcsbios(message: &str) -> io::Result<()> {
-    let mut stream = TcpStream::connect(("127.0.0.1", 7778))?;
-    stream.write_all(message.as_bytes())?;
-    stream.write_all(b"\n")?;
-    stream.flush()?;
-    Ok(())
-}
\ No newline at end of file
+// Key down and key up of a momentary button or rocker, sent as they happen
+pub fn send_button_state(state: &str){
+    queue_command(state);
+}
+
+// Momentary button or rocker pushed and let go like a button, for keys that repeat
+pub fn send_button_pulse(held: &str, released: &str){
+    queue_press(held, released, Duration::from_millis(BUTTON_SLEEP_DELAY));
+}
+
+// Tests that go through the published snapshot take turns
+#[cfg(test)]
+pub(crate) static BIOS_TEST_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+// One TCP session with a local server, ends when the server closes it
+#[cfg(test)]
+pub(crate) fn read_tcp_session() -> std::thread::JoinHandle<()> {
+    std::thread::spawn(|| {
+        let mut memory = MemoryWriter::new();
+        let _ = read_tcp_stream(&mut memory);
+        mark_disconnected(&mut memory);
+    })
+}
+
+// Listens for the UDP export until stop is set
+#[cfg(test)]
+pub(crate) fn read_udp_session(stop: std::sync::Arc<AtomicBool>) -> std::thread::JoinHandle<()> {
+    std::thread::spawn(move || {
+        let mut memory = MemoryWriter::new();
+        let _ = read_udp_stream(&mut memory, &stop);
+        mark_disconnected(&mut memory);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memoryImageHelper::snapshot;
+    use std::sync::Arc;
+    use std::thread;
+
+    // sync, one block at address, more blocks may follow
+    fn block(address: u16, data: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0x55, 0x55, 0x55, 0x55];
+        frame.extend_from_slice(&address.to_le_bytes());
+        frame.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        frame.extend_from_slice(data);
+        frame
+    }
+
+    fn shown(address: u16) -> Vec<u8> {
+        snapshot().bytes(address, 8).to_vec()
+    }
+
+    #[test]
+    fn udp_export_lands_in_the_memory_image() {
+        let _lock = BIOS_TEST_LOCK.lock().unwrap_or_else(|p| p.into_inner());
+        let stop = Arc::new(AtomicBool::new(false));
+        let reader = read_udp_session(stop.clone());
+        let sender = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).unwrap();
+        sender.set_multicast_loop_v4(true).unwrap();
+        sender.set_multicast_ttl_v4(0).unwrap();
+        let send = |data: &[u8]| sender.send_to(data, (MULTICAST_GROUP, ports().export)).unwrap();
+
+        let mut frame = block(0x11c0, b"UDP TEST");
+        frame.extend_from_slice(&[0xfe, 0xff, 0x02, 0x00, 0x01, 0x00]);
+        // the reader joins the group some time after it was started
+        for _ in 0..100 {
+            send(&frame);
+            thread::sleep(Duration::from_millis(20));
+            if shown(0x11c0) == b"UDP TEST" {
+                break;
+            }
+        }
+        assert_eq!(shown(0x11c0), b"UDP TEST");
+        assert!(is_connected());
+
+        // published once the update counter of the frame came in
+        send(&block(0x11c0, b"NEXT ONE"));
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(shown(0x11c0), b"UDP TEST");
+        send(&block(0xfffe, &[0x02, 0x00]));
+        for _ in 0..50 {
+            if shown(0x11c0) == b"NEXT ONE" {
+                break;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert_eq!(shown(0x11c0), b"NEXT ONE");
+
+        // gone before the next test takes the lock
+        stop.store(true, Ordering::SeqCst);
+        reader.join().unwrap();
+        assert!(!is_connected());
+        assert_eq!(shown(0x11c0), [0; 8]);
+    }
+}
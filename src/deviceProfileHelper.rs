@@ -0,0 +1,182 @@
+#![allow(non_snake_case)]
+// Supported WinWing units and how to recognise them
+
+use anyhow::{anyhow, Context, Result};
+use hidapi::{DeviceInfo, HidApi, HidDevice};
+use std::collections::HashSet;
+use std::ffi::CString;
+use std::sync::{LazyLock, Mutex};
+
+const WINWING_VID: u16 = 0x4098;
+// First bytes of every init record, they differ between the units
+pub(crate) const PFP3N_HEADER: [u8; 2] = [0x31, 0xBB];
+pub(crate) const PFP7_HEADER: [u8; 2] = [0x33, 0xBB];
+pub(crate) const MCDU_HEADER: [u8; 2] = [0x32, 0xBB];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum InitSequence {
+    // compiled from a font.txt style description at start up, with the
+    // record header the unit expects
+    Font(&'static str, [u8; 2]),
+    // replayed from a capture, see captureHelper
+    Capture(&'static str)
+}
+
+pub(crate) struct DeviceProfile {
+    pub(crate) name: &'static str,
+    pub(crate) vid: u16,
+    // empty if the PID isn't known, the product string has to match then
+    pub(crate) pids: &'static [u16],
+    // any of these in the product string identifies the unit, empty accepts any
+    pub(crate) product_patterns: &'static [&'static str],
+    pub(crate) cols: usize,
+    pub(crate) rows: usize,
+    // key layout, keys/devices/<keys>.json
+    pub(crate) keys: &'static str,
+    pub(crate) init: InitSequence
+}
+
+// More specific profiles first, the FO variant would otherwise be taken for a captain unit
+pub(crate) static PROFILES: [DeviceProfile; 4] = [
+    DeviceProfile {
+        name: "PFP 3N FO",
+        vid: WINWING_VID,
+        pids: &[],
+        product_patterns: &["PFP-3N-FO", "PFP-3N-FIRST"],
+        cols: 24,
+        rows: 14,
+        keys: "pfp",
+        // not captured, taken to be the same as the captain unit
+        init: InitSequence::Font("font.txt", PFP3N_HEADER),
+    },
+    DeviceProfile {
+        name: "PFP 3N",
+        vid: WINWING_VID,
+        pids: &[0xbb35],
+        product_patterns: &["PFP-3N-CAPTAIN"],
+        cols: 24,
+        rows: 14,
+        keys: "pfp",
+        init: InitSequence::Font("font.txt", PFP3N_HEADER),
+    },
+    DeviceProfile {
+        name: "PFP 7",
+        vid: WINWING_VID,
+        pids: &[0xbb37],
+        product_patterns: &["PFP-7"],
+        cols: 24,
+        rows: 14,
+        keys: "pfp",
+        // the vendor software sends it other glyphs, font.txt keeps the symbols the same on all units
+        init: InitSequence::Font("font.txt", PFP7_HEADER),
+    },
+    // Untested, PIDs (captain, observer, FO), header and key bits taken from other MCDU tools.
+    // Its keys are named after the PFP key in the same spot, so the aircraft bindings fit.
+    DeviceProfile {
+        name: "MCDU",
+        vid: WINWING_VID,
+        pids: &[0xbb36, 0xbb3a, 0xbb3e],
+        product_patterns: &["MCDU"],
+        cols: 24,
+        rows: 14,
+        keys: "mcdu",
+        init: InitSequence::Font("font.txt", MCDU_HEADER),
+    },
+];
+
+impl DeviceProfile {
+    fn matches(&self, info: &DeviceInfo, check_product: bool) -> bool {
+        if info.vendor_id() != self.vid {
+            return false;
+        }
+        let product = info.product_string().unwrap_or("").to_uppercase();
+        let pid_ok = self.pids.contains(&info.product_id());
+        let product_ok = self.product_patterns.is_empty()
+            || self.product_patterns.iter().any(|p| product.contains(p));
+        if check_product {
+            product_ok && (pid_ok || self.pids.is_empty())
+        } else {
+            pid_ok
+        }
+    }
+}
+
+// Attached unit with the profile it was recognised by
+pub(crate) struct DetectedDevice {
+    pub(crate) profile: &'static DeviceProfile,
+    pub(crate) info: DeviceInfo
+}
+
+// Every supported unit that is plugged in. Units are matched by product string
+// first, then by VID/PID alone in case the firmware reports an unexpected name.
+pub(crate) fn detect_devices(hid_api: &HidApi) -> Vec<DetectedDevice> {
+    let mut found: Vec<DetectedDevice> = Vec::new();
+    for check_product in [true, false] {
+        for info in hid_api.device_list() {
+            // one unit exposes several interfaces, the first one stands for it.
+            // Identical units share VID, PID and serial, only the path tells them apart.
+            if info.interface_number() > 0 || found.iter().any(|d| d.info.path() == info.path()) {
+                continue;
+            }
+            if let Some(profile) = PROFILES.iter().find(|p| p.matches(info, check_product)) {
+                found.push(DetectedDevice { profile, info: info.clone() });
+            }
+        }
+    }
+    // stable order, so roles stay with the same USB port between runs
+    found.sort_by(|a, b| a.info.path().cmp(b.info.path()));
+    found
+}
+
+// Paths of the units that have a pipeline, so a unit that comes back on a new
+// path isn't taken by the pipeline of an identical one
+static CLAIMED: LazyLock<Mutex<HashSet<CString>>> = LazyLock::new(|| Mutex::new(HashSet::new()));
+
+// Which unit a pipeline drives, used to open it again after it was unplugged
+pub(crate) struct UnitLocator {
+    profile: &'static DeviceProfile,
+    serial: Option<String>,
+    path: Mutex<CString>
+}
+
+impl UnitLocator {
+    pub(crate) fn new(detected: &DetectedDevice) -> Self {
+        let path = detected.info.path().to_owned();
+        CLAIMED.lock().unwrap().insert(path.clone());
+        UnitLocator { profile: detected.profile, serial: detected.info.serial_number().map(str::to_string), path: Mutex::new(path) }
+    }
+
+    // Opens the unit on its path. Some systems number the devices again after a
+    // replug, then a unit of the same profile and serial that no pipeline has is taken.
+    pub(crate) fn open(&self) -> Result<HidDevice> {
+        let hid_api = HidApi::new().context("Failed to initialize HID API")?;
+        let units = detect_devices(&hid_api);
+        let mut path = self.path.lock().unwrap();
+        let mut claimed = CLAIMED.lock().unwrap();
+        let same_profile = |d: &&DetectedDevice| std::ptr::eq(d.profile, self.profile);
+        let detected = units
+            .iter()
+            .filter(same_profile)
+            .find(|d| d.info.path() == path.as_c_str())
+            .or_else(|| units.iter().filter(same_profile).find(|d| {
+                d.info.serial_number() == self.serial.as_deref() && !claimed.contains(d.info.path())
+            }))
+            .ok_or_else(|| anyhow!("{} is not connected", self.profile.name))?;
+
+        let device = hid_api
+            .open_path(detected.info.path())
+            .with_context(|| format!("Failed to open HID path {}", detected.info.path().to_string_lossy()))?;
+        if detected.info.path() != path.as_c_str() {
+            claimed.remove(path.as_c_str());
+            *path = detected.info.path().to_owned();
+            claimed.insert(path.clone());
+        }
+        Ok(device)
+    }
+}
+
+impl Drop for UnitLocator {
+    fn drop(&mut self) {
+        CLAIMED.lock().unwrap().remove(self.path.get_mut().unwrap().as_c_str());
+    }
+}
@@ -0,0 +1,191 @@
+#![allow(non_snake_case)]
+// Software stand-in for the PFP display. Takes the same packets the real device
+// gets and rebuilds the 24x14 grid from them, so pages can be looked at (or
+// compared) without hardware attached.
+
+use anyhow::{bail, Result};
+use std::fmt::Write;
+use std::sync::Mutex;
+
+use crate::screenHelper::{Cell, Screen, SCREEN_COLS, SCREEN_ROWS};
+use crate::transportHelper::Transport;
+use crate::types::{Bg, Fg, FontSize, TextStyle};
+
+const DISPLAY_REPORT: u8 = 0xF2;
+const CELL_COUNT: usize = SCREEN_COLS * SCREEN_ROWS;
+
+pub(crate) struct Emulator {
+    screen: Screen,
+    // next cell the display writes to, 0xF2 packets don't carry a position
+    cursor: usize,
+    // 0xF2 reports so far, a partial update doesn't wrap the cursor
+    reports: u64
+}
+
+impl Default for Emulator {
+    fn default() -> Self {
+        Emulator::new()
+    }
+}
+
+impl Emulator {
+    pub(crate) fn new() -> Self {
+        Emulator { screen: Screen::new(), cursor: 0, reports: 0 }
+    }
+
+    // Accepts any report, only 0xF2 ones change the screen.
+    // Init (0xF0) and backlight (0x02) reports are ignored.
+    pub(crate) fn write(&mut self, packet: &[u8]) -> Result<()> {
+        if packet.first() != Some(&DISPLAY_REPORT) {
+            return Ok(());
+        }
+        if packet.len() != 64 {
+            bail!("0xF2 report has {} bytes, expected 64", packet.len());
+        }
+        self.reports += 1;
+        for triplet in packet[1..].chunks_exact(3) {
+            let style = match TextStyle::decode([triplet[0], triplet[1]]) {
+                Some(style) => style,
+                None => bail!(
+                    "Cell {} (row {}, col {}): unknown colour word {:02x} {:02x}",
+                    self.cursor, self.cursor / SCREEN_COLS, self.cursor % SCREEN_COLS, triplet[0], triplet[1]
+                ),
+            };
+            self.screen.set(self.cursor / SCREEN_COLS, self.cursor % SCREEN_COLS, Cell { ch: triplet[2] as char, style });
+            self.cursor += 1;
+            if self.cursor == CELL_COUNT {
+                self.cursor = 0;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn screen(&self) -> &Screen {
+        &self.screen
+    }
+
+    pub(crate) fn reports(&self) -> u64 {
+        self.reports
+    }
+}
+
+// Lets the emulator stand in for the HID device. It has no keys, reads never return anything.
+impl Transport for Mutex<Emulator> {
+    fn write(&self, data: &[u8]) -> Result<usize> {
+        let mut emulator = match self.lock() {
+            Ok(e) => e,
+            Err(p) => p.into_inner(),
+        };
+        emulator.write(data)?;
+        Ok(data.len())
+    }
+
+    fn read(&self, _buf: &mut [u8]) -> Result<usize> {
+        Ok(0)
+    }
+
+    fn set_blocking_mode(&self, _blocking: bool) -> Result<()> {
+        Ok(())
+    }
+}
+
+pub(crate) fn render_plain(screen: &Screen) -> String {
+    let mut out = String::with_capacity((SCREEN_COLS + 1) * SCREEN_ROWS);
+    for row in screen.cells().chunks(SCREEN_COLS) {
+        out.extend(row.iter().map(|cell| cell.ch));
+        out.push('\n');
+    }
+    out
+}
+
+// Approximations of the palette uploaded in fontHelper
+fn fg_rgb(fg: Fg) -> (u8, u8, u8) {
+    match fg {
+        Fg::Orange => (255, 165, 0),
+        Fg::White => (255, 255, 255),
+        Fg::Cyan => (0, 255, 255),
+        Fg::Green => (0, 255, 61),
+        Fg::Magenta => (255, 99, 255),
+        Fg::Red => (255, 0, 0),
+        Fg::Yellow => (255, 255, 0),
+    }
+}
+
+fn bg_rgb(bg: Bg) -> (u8, u8, u8) {
+    match bg {
+        Bg::Black => (0, 0, 0),
+        Bg::Green => (0, 120, 30),
+        Bg::Gray => (97, 92, 66),
+        Bg::Orange => (160, 100, 0),
+        Bg::Purple => (110, 40, 110),
+    }
+}
+
+// 24 bit colour escape codes, the small font is shown in italics
+pub(crate) fn render_ansi(screen: &Screen) -> String {
+    let mut out = String::new();
+    for row in screen.cells().chunks(SCREEN_COLS) {
+        let mut last: Option<TextStyle> = None;
+        for cell in row {
+            if last != Some(cell.style) {
+                let (fr, fg, fb) = fg_rgb(cell.style.fg);
+                let (br, bg, bb) = bg_rgb(cell.style.bg);
+                let italic = if cell.style.size == FontSize::Small { 3 } else { 23 };
+                let _ = write!(out, "\x1b[{};38;2;{};{};{};48;2;{};{};{}m", italic, fr, fg, fb, br, bg, bb);
+                last = Some(cell.style);
+            }
+            out.push(cell.ch);
+        }
+        out.push_str("\x1b[0m\n");
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    use crate::controlReferenceHelper::load_control_references;
+    use crate::memoryImageHelper::MemoryImage;
+    use crate::mockBiosHelper::load_script;
+    use crate::moduleDataProcessorHelper::{get_A10C2_text, get_AH64D_text, get_AV8B_text};
+    use crate::writeHelper::DisplayWriter;
+
+    // Export memory as the mock DCS-BIOS would serve it
+    fn script_image(path: &str) -> MemoryImage {
+        load_control_references("controls").unwrap();
+        MemoryImage::from_words(&load_script(path).unwrap().words)
+    }
+
+    // Sends the page through the same packets the PFP gets and compares what the
+    // emulated display shows with the expected grid
+    fn assert_golden(screen: Screen, golden: &str) {
+        let emulator = Mutex::new(Emulator::new());
+        DisplayWriter::new().send_text_to_disp(&emulator, 0.0, &screen).unwrap();
+        let emulator = emulator.lock().unwrap();
+        assert_eq!(emulator.reports(), 16);
+        assert_eq!(emulator.screen(), &screen, "colours or font size lost on the way");
+        assert_eq!(render_plain(emulator.screen()), fs::read_to_string(golden).unwrap());
+    }
+
+    #[test]
+    fn a10c_cdu_page() {
+        assert_golden(get_A10C2_text(&script_image("mock/a10c.txt")), "mock/a10c_screen.txt");
+    }
+
+    #[test]
+    fn ah64d_cpg_page() {
+        assert_golden(get_AH64D_text(&script_image("mock/ah64d.txt"), true), "mock/ah64d_screen.txt");
+    }
+
+    #[test]
+    fn av8b_page() {
+        assert_golden(get_AV8B_text(&script_image("mock/av8b.txt")), "mock/av8b_screen.txt");
+    }
+
+    #[test]
+    fn rejects_short_report() {
+        assert!(Emulator::new().write(&[DISPLAY_REPORT, 0x42, 0x00, b'A']).is_err());
+    }
+}
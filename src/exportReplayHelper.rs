@@ -0,0 +1,187 @@
+#![allow(non_snake_case)]
+// Recording and replay of the raw DCS-BIOS export stream, so the pages can be
+// worked on without DCS. A recording is one line per read from DCS-BIOS:
+//   +<seconds since the first read> <hex bytes>
+// Lines starting with # are comments.
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::biosServerHelper::serve_export;
+use crate::dcsBiosHelper::{ports, BiosConnection, BiosPorts};
+
+// written to disk this often, a bridge that is killed loses at most that much
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+struct Recording {
+    file: BufWriter<File>,
+    // set by the first read, so a recording doesn't start with the wait for DCS
+    start: Option<Instant>,
+    last_flush: Instant
+}
+
+pub(crate) struct ExportRecorder(Mutex<Recording>);
+
+impl ExportRecorder {
+    pub(crate) fn create(path: &str) -> Result<Self> {
+        let file = File::create(path).with_context(|| format!("Failed to create export recording {}", path))?;
+        let mut file = BufWriter::new(file);
+        writeln!(file, "# DCS-BIOS export stream, +seconds hex")?;
+        Ok(ExportRecorder(Mutex::new(Recording { file, start: None, last_flush: Instant::now() })))
+    }
+
+    pub(crate) fn record(&self, data: &[u8]) {
+        let mut recording = self.0.lock().unwrap_or_else(|p| p.into_inner());
+        let secs = recording.start.get_or_insert_with(Instant::now).elapsed().as_secs_f64();
+        let mut res = writeln!(recording.file, "+{:.3} {}", secs, hex::encode(data));
+        if res.is_ok() && recording.last_flush.elapsed() >= FLUSH_INTERVAL {
+            recording.last_flush = Instant::now();
+            res = recording.file.flush();
+        }
+        // a failing recorder must not take the bridge down with it
+        if let Err(e) = res {
+            eprintln!("PFP_WRITER: export recording failed: {}", e);
+        }
+    }
+}
+
+pub(crate) struct RecordedRead {
+    at: Duration,
+    data: Vec<u8>
+}
+
+pub(crate) fn load_recording(path: &str) -> Result<Vec<RecordedRead>> {
+    let text = fs::read_to_string(path).with_context(|| format!("Failed to open export recording {}", path))?;
+    let mut reads = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line_no = i + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (time, data) = line
+            .split_once(' ')
+            .ok_or_else(|| anyhow!("Line {}: expected '+<seconds> <hex>', got '{}'", line_no, line))?;
+        let secs: f64 = time
+            .strip_prefix('+')
+            .and_then(|t| t.parse().ok())
+            .filter(|t: &f64| t.is_finite() && *t >= 0.0)
+            .ok_or_else(|| anyhow!("Line {}: invalid time '{}'", line_no, time))?;
+        let data = hex::decode(data.trim()).with_context(|| format!("Line {}: invalid hex", line_no))?;
+        reads.push(RecordedRead { at: Duration::from_secs_f64(secs), data });
+    }
+    if reads.is_empty() {
+        bail!("{} holds no export data", path);
+    }
+    Ok(reads)
+}
+
+// Sends the recording over and over with the recorded timing, speed 2.0 plays twice as fast
+fn play(reads: &[RecordedRead], speed: f64, send: &mut dyn FnMut(&[u8]) -> io::Result<()>) -> io::Result<()> {
+    loop {
+        let start = Instant::now();
+        for read in reads {
+            let due = start + read.at.div_f64(speed);
+            if let Some(wait) = due.checked_duration_since(Instant::now()) {
+                thread::sleep(wait);
+            }
+            send(&read.data)?;
+        }
+    }
+}
+
+// Plays the recording to every client of the local DCS-BIOS. Commands are
+// only printed, the recording doesn't react to them. Returns the ports it got.
+pub(crate) fn serve_recording(reads: Vec<RecordedRead>, speed: f64, connection: BiosConnection) -> Result<BiosPorts> {
+    serve_export(
+        "REPLAY",
+        connection,
+        ports(),
+        Arc::new(move |send| play(&reads, speed, send)),
+        Arc::new(|command| println!("REPLAY: received {}", command)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // File of its own in the temp directory, tests run in parallel
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir().join(format!("pfp_writer_{}_{}.txt", std::process::id(), name)).to_string_lossy().into_owned()
+    }
+
+    fn load_text(name: &str, text: &str) -> Result<Vec<RecordedRead>> {
+        let path = temp_path(name);
+        fs::write(&path, text).unwrap();
+        let reads = load_recording(&path);
+        fs::remove_file(&path).unwrap();
+        reads
+    }
+
+    fn error(name: &str, text: &str) -> String {
+        format!("{:#}", load_text(name, text).err().unwrap())
+    }
+
+    #[test]
+    fn recording_loads_back_with_its_timing() {
+        let path = temp_path("round_trip");
+        let recorder = ExportRecorder::create(&path).unwrap();
+        let sent: [&[u8]; 3] = [&[0x55, 0x55, 0x55, 0x55], &[0xfe, 0xff, 0x02, 0x00], &[0x00]];
+        for data in sent {
+            recorder.record(data);
+            thread::sleep(Duration::from_millis(50));
+        }
+        // closing the file writes the rest
+        drop(recorder);
+
+        let reads = load_recording(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let data: Vec<&[u8]> = reads.iter().map(|r| r.data.as_slice()).collect();
+        assert_eq!(data, sent);
+        assert_eq!(reads[0].at, Duration::ZERO);
+        for pair in reads.windows(2) {
+            let gap = pair[1].at - pair[0].at;
+            assert!(gap >= Duration::from_millis(45) && gap < Duration::from_millis(200), "{:?}", gap);
+        }
+    }
+
+    #[test]
+    fn comments_and_blank_lines_are_skipped() {
+        let reads = load_text("comments", "# header\n\n+0.000 5555\n  +0.5 feff  \n").unwrap();
+        assert_eq!(reads.len(), 2);
+        assert_eq!(reads[1].at, Duration::from_millis(500));
+        assert_eq!(reads[1].data, [0xfe, 0xff]);
+    }
+
+    #[test]
+    fn malformed_lines_name_the_line() {
+        assert_eq!(error("no_data", "+0.000 5555\n5555\n"), "Line 2: expected '+<seconds> <hex>', got '5555'");
+        assert_eq!(error("no_plus", "# header\n0.5 5555\n"), "Line 2: invalid time '0.5'");
+        assert_eq!(error("negative", "+-1 5555\n"), "Line 1: invalid time '+-1'");
+        assert!(error("bad_hex", "+0 5555\n\n+1 55z5\n").starts_with("Line 3: invalid hex"));
+        assert!(error("empty", "# header\n").ends_with("holds no export data"));
+    }
+
+    #[test]
+    fn play_scales_the_timing_and_starts_over() {
+        let reads = load_text("play", "+0 01\n+0.1 02\n+0.2 03\n").unwrap();
+        let start = Instant::now();
+        let mut sent = Vec::new();
+        let res = play(&reads, 2.0, &mut |data| {
+            sent.push((data[0], start.elapsed()));
+            if sent.len() == 4 { Err(io::Error::other("done")) } else { Ok(()) }
+        });
+        assert!(res.is_err());
+        let bytes: Vec<u8> = sent.iter().map(|s| s.0).collect();
+        assert_eq!(bytes, [1, 2, 3, 1]);
+        // twice as fast, the last read of 0.2s is due after 0.1s
+        let last = sent[2].1;
+        assert!(last >= Duration::from_millis(95) && last < Duration::from_millis(180), "{:?}", last);
+        assert!(sent[3].1 - last < Duration::from_millis(50), "{:?}", sent[3].1 - last);
+    }
+}
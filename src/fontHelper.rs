@@ -0,0 +1,476 @@
+#![allow(non_snake_case)]
+// Compiles the glyph description in font.txt into the 0xF0 init packets
+// that upload the two display fonts (large and small) and the palette.
+//
+// Layout of the init stream (reverse engineered from PFP3N.txt):
+//   0xF0 report: [0xF0, 0x00, seq, len, len bytes of stream, zero padding to 64]
+//   flush report after every transaction: [0xF0, 0x01, seq, 0x00, ...]
+//   The stream is a list of records:
+//     [h0, h1, 0x00, 0x00, type, 0x01, 0x00, 0x00, token u32, flag, len u32, payload]
+//   The two header bytes depend on the unit, 0x31 0xBB for the PFP 3N and
+//   0x33 0xBB for the PFP 7 (PFP7 wireshark.txt), see the device profiles.
+//   A font is one header record (0x06) followed by 512 byte chunks (0x07) of
+//   glyph data, each chunk committed with a 0x05 record carrying its token.
+//   Records written together share a token, the vendor software takes them
+//   from a clock, here they just count up. The output is checked against the
+//   uploads in PFP3N.txt and PFP7 wireshark.txt, see the tests.
+//   A glyph is its codepoint (u32) followed by 32 rows of 24 pixels, 3 bytes per row,
+//   most significant bit is the leftmost pixel.
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::fs;
+
+use crate::screenHelper::{SCREEN_COLS, SCREEN_ROWS};
+
+pub(crate) const GLYPH_WIDTH: usize = 24;
+pub(crate) const GLYPH_ROWS: usize = 32;
+const GLYPH_SIZE: usize = 4 + GLYPH_ROWS * GLYPH_WIDTH / 8;
+// The vendor software always uploads 116 slots, unused ones are left empty
+const FONT_SLOTS: usize = 116;
+const CHUNK_SIZE: usize = 512;
+const PACKET_PAYLOAD: usize = 60;
+const FIRST_SEQ: u8 = 2;
+const FIRST_TOKEN: u32 = 0x0004_5130;
+
+const REC_COMMIT: u8 = 0x05;
+const REC_FONT_HEADER: u8 = 0x06;
+const REC_FONT_CHUNK: u8 = 0x07;
+
+// Palette entries as sent by the vendor software: (table, [b, g, r, a])
+const PALETTE: [(u16, [u8; 4]); 27] = [
+    (1, [0x01, 0x00, 0x00, 0x00]),
+    (1, [0x02, 0x00, 0x00, 0x00]),
+    (2, [0x00, 0x00, 0x00, 0xff]), // black
+    (2, [0x00, 0xa5, 0xff, 0xff]), // orange
+    (2, [0xff, 0xff, 0xff, 0xff]), // white
+    (2, [0xff, 0xff, 0x00, 0xff]), // cyan
+    (2, [0x3d, 0xff, 0x00, 0xff]), // green
+    (2, [0xff, 0x63, 0xff, 0xff]), // magenta
+    (2, [0x00, 0x00, 0xff, 0xff]), // red
+    (2, [0x00, 0xff, 0xff, 0xff]), // yellow
+    (2, [0x42, 0x5c, 0x61, 0xff]),
+    (2, [0x77, 0x77, 0x77, 0xff]),
+    (2, [0x5e, 0x73, 0x79, 0xff]),
+    (3, [0x00, 0x00, 0x00, 0xff]),
+    (3, [0x00, 0xa5, 0xff, 0xff]),
+    (3, [0xff, 0xff, 0xff, 0xff]),
+    (3, [0xff, 0xff, 0x00, 0xff]),
+    (3, [0x3d, 0xff, 0x00, 0xff]),
+    (3, [0xff, 0x63, 0xff, 0xff]),
+    (3, [0x00, 0x00, 0xff, 0xff]),
+    (3, [0x00, 0xff, 0xff, 0xff]),
+    (3, [0x42, 0x5c, 0x61, 0xff]),
+    (3, [0x77, 0x77, 0x77, 0xff]),
+    (3, [0x5e, 0x73, 0x79, 0xff]),
+    (4, [0x00, 0x00, 0x00, 0x00]),
+    (4, [0x01, 0x00, 0x00, 0x00]),
+    (4, [0x02, 0x00, 0x00, 0x00]),
+];
+
+#[derive(Clone, Debug)]
+pub(crate) struct Glyph {
+    pub(crate) codepoint: u32,
+    pub(crate) rows: [u32; GLYPH_ROWS]
+}
+
+// Large font first, small font second, same order the display numbers them
+#[derive(Clone, Debug, Default)]
+pub(crate) struct FontSet {
+    pub(crate) large: Vec<Glyph>,
+    pub(crate) small: Vec<Glyph>
+}
+
+// Parses the font description:
+//   font large | font small   selects the font the following glyphs belong to
+//   glyph <hex codepoint> ... starts a glyph, everything after the codepoint is a label
+//   32 lines of 24 pixels     '#' is a lit pixel, '.' is an unlit one
+// Everything after '#' at the start of a line is a comment.
+pub(crate) fn parse_font(text: &str) -> Result<FontSet> {
+    let mut fonts = FontSet::default();
+    let mut current: Option<&mut Vec<Glyph>> = None;
+    let mut glyph: Option<(usize, Glyph, usize)> = None; // (line, glyph, rows read)
+
+    for (lineno, line) in text.lines().enumerate() {
+        let line_no = lineno + 1;
+        let trimmed = line.trim();
+
+        if let Some((start, g, rows)) = glyph.as_mut() {
+            if *rows < GLYPH_ROWS {
+                if trimmed.chars().count() != GLYPH_WIDTH {
+                    bail!("Line {}: glyph {:04X} (line {}) row {} must be {} pixels wide, got '{}'",
+                        line_no, g.codepoint, start, *rows + 1, GLYPH_WIDTH, trimmed);
+                }
+                let mut bits: u32 = 0;
+                for ch in trimmed.chars() {
+                    bits <<= 1;
+                    match ch {
+                        '#' => bits |= 1,
+                        '.' => {},
+                        _ => bail!("Line {}: invalid pixel '{}', use '#' or '.'", line_no, ch),
+                    }
+                }
+                g.rows[*rows] = bits;
+                *rows += 1;
+                if *rows == GLYPH_ROWS {
+                    let (_, done, _) = glyph.take().unwrap();
+                    current.as_mut().unwrap().push(done);
+                }
+                continue;
+            }
+        }
+
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        let mut words = trimmed.split_whitespace();
+        match words.next() {
+            Some("font") => {
+                current = match words.next() {
+                    Some("large") => Some(&mut fonts.large),
+                    Some("small") => Some(&mut fonts.small),
+                    other => bail!("Line {}: unknown font '{}', expected 'large' or 'small'", line_no, other.unwrap_or("")),
+                };
+            }
+            Some("glyph") => {
+                if current.is_none() {
+                    bail!("Line {}: glyph before any 'font' line", line_no);
+                }
+                let cp_text = words.next().ok_or_else(|| anyhow!("Line {}: glyph without codepoint", line_no))?;
+                let codepoint = u32::from_str_radix(cp_text.trim_start_matches("0x"), 16)
+                    .with_context(|| format!("Line {}: invalid codepoint '{}'", line_no, cp_text))?;
+                glyph = Some((line_no, Glyph { codepoint, rows: [0; GLYPH_ROWS] }, 0));
+            }
+            Some(other) => bail!("Line {}: unexpected '{}'", line_no, other),
+            None => {}
+        }
+    }
+
+    if let Some((start, g, rows)) = glyph {
+        bail!("Glyph {:04X} (line {}) only has {} of {} rows", g.codepoint, start, rows, GLYPH_ROWS);
+    }
+    for (name, font) in [("large", &fonts.large), ("small", &fonts.small)] {
+        if font.len() > FONT_SLOTS {
+            bail!("Font {} has {} glyphs, the display takes at most {}", name, font.len(), FONT_SLOTS);
+        }
+    }
+    Ok(fonts)
+}
+
+pub(crate) fn load_font(path: &str) -> Result<FontSet> {
+    let text = fs::read_to_string(path).with_context(|| format!("Failed to read font file {}", path))?;
+    parse_font(&text).with_context(|| format!("Invalid font file {}", path))
+}
+
+fn glyph_data(glyphs: &[Glyph]) -> Vec<u8> {
+    let mut data = vec![0u8; FONT_SLOTS * GLYPH_SIZE];
+    for (slot, glyph) in glyphs.iter().enumerate() {
+        let rec = &mut data[slot * GLYPH_SIZE..(slot + 1) * GLYPH_SIZE];
+        rec[0..4].copy_from_slice(&glyph.codepoint.to_le_bytes());
+        for (row, bits) in glyph.rows.iter().enumerate() {
+            rec[4 + row * 3..7 + row * 3].copy_from_slice(&bits.to_be_bytes()[1..]);
+        }
+    }
+    data
+}
+
+// Builds the record stream and splits it into reports
+struct InitBuilder {
+    header: [u8; 2],
+    packets: Vec<Vec<u8>>,
+    pending: Vec<u8>,
+    seq: u8,
+    token: u32
+}
+
+impl InitBuilder {
+    fn new(header: [u8; 2]) -> Self {
+        InitBuilder { header, packets: Vec::new(), pending: Vec::new(), seq: FIRST_SEQ, token: FIRST_TOKEN }
+    }
+
+    fn next_token(&mut self) -> u32 {
+        let token = self.token;
+        self.token = self.token.wrapping_add(1);
+        token
+    }
+
+    fn record(&mut self, rec_type: u8, token: u32, flag: u8, payload: &[u8]) {
+        self.pending.extend_from_slice(&self.header);
+        self.pending.extend_from_slice(&[0x00, 0x00, rec_type, 0x01, 0x00, 0x00]);
+        self.pending.extend_from_slice(&token.to_le_bytes());
+        self.pending.push(flag);
+        self.pending.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        self.pending.extend_from_slice(payload);
+    }
+
+    fn commit(&mut self, token: u32) {
+        self.record(REC_COMMIT, token, 0x01, &[]);
+    }
+
+    // Sends everything queued since the last write as 0xF0 reports
+    fn write(&mut self) {
+        let pending = std::mem::take(&mut self.pending);
+        for chunk in pending.chunks(PACKET_PAYLOAD) {
+            let mut packet = vec![0xF0, 0x00, self.seq, chunk.len() as u8];
+            packet.extend_from_slice(chunk);
+            packet.resize(64, 0x00);
+            self.packets.push(packet);
+            self.seq = self.seq.wrapping_add(1);
+        }
+    }
+
+    // Ends a transaction
+    fn flush(&mut self) {
+        self.write();
+        let mut packet = vec![0xF0, 0x01, self.seq, 0x00];
+        packet.resize(64, 0x00);
+        self.packets.push(packet);
+        self.seq = self.seq.wrapping_add(1);
+    }
+
+    // header_alone sends the header record in a report of its own instead of
+    // in front of the first chunk, the vendor software does it for the large font only
+    fn font(&mut self, font_id: u32, glyphs: &[Glyph], header_alone: bool) {
+        let data = glyph_data(glyphs);
+        let chunks = data.len().div_ceil(CHUNK_SIZE);
+
+        let mut header: Vec<u8> = Vec::with_capacity(25);
+        header.extend_from_slice(&font_id.to_le_bytes());
+        header.extend_from_slice(&(chunks as u16).to_le_bytes());
+        header.extend_from_slice(&(GLYPH_ROWS as u16).to_le_bytes());
+        header.extend_from_slice(&(GLYPH_SIZE as u32).to_le_bytes());
+        header.extend_from_slice(&(FONT_SLOTS as u32).to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(&((data.len() + 25) as u32).to_le_bytes());
+        header.push(0x00);
+        let mut token = self.next_token();
+        self.record(REC_FONT_HEADER, token, 0x00, &header);
+        if header_alone {
+            self.write();
+        }
+
+        for (i, chunk) in data.chunks(CHUNK_SIZE).enumerate() {
+            let mut payload: Vec<u8> = Vec::with_capacity(12 + chunk.len());
+            payload.extend_from_slice(&font_id.to_le_bytes());
+            payload.extend_from_slice(&((i * CHUNK_SIZE) as u32).to_le_bytes());
+            payload.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+            payload.extend_from_slice(chunk);
+
+            // records sent together share a token
+            if i > 0 || header_alone {
+                token = self.next_token();
+            }
+            self.record(REC_FONT_CHUNK, token, 0x00, &payload);
+            self.commit(token);
+            self.flush();
+        }
+    }
+}
+
+// Generates the complete init sequence: both fonts, screen geometry, palette
+// and display setup, in the order the WinWing software sends them.
+// header is the record header of the unit, e.g. [0x31, 0xBB] for the PFP 3N.
+pub(crate) fn compile_init_packets(fonts: &FontSet, header: [u8; 2]) -> Vec<Vec<u8>> {
+    let mut b = InitBuilder::new(header);
+    b.font(1, &fonts.large, true);
+    b.font(2, &fonts.small, false);
+
+    // Backlight, sent by the vendor software between the font upload and the layout
+    let mut backlight = vec![0x02, header[0], header[1], 0x00, 0x00, 0x03, 0x49, 0x01, 0xff];
+    backlight.resize(14, 0x00);
+    b.packets.push(backlight);
+
+    // Screen geometry
+    let token = b.next_token();
+    b.record(0x1e, token, 0x00, &[]);
+    b.write();
+    let mut geometry: Vec<u8> = Vec::with_capacity(8);
+    for value in [0x32, 0x13, SCREEN_ROWS as u16, SCREEN_COLS as u16] {
+        geometry.extend_from_slice(&value.to_le_bytes());
+    }
+    b.record(0x18, token, 0x00, &geometry);
+    b.commit(token);
+    b.flush();
+
+    // Palette
+    let token = b.next_token();
+    for (i, (table, colour)) in PALETTE.iter().enumerate() {
+        let mut entry: Vec<u8> = Vec::with_capacity(14);
+        entry.extend_from_slice(&table.to_le_bytes());
+        entry.extend_from_slice(colour);
+        entry.push(i as u8 + 2);
+        entry.resize(14, 0x00);
+        b.record(0x19, token, 0x00, &entry);
+    }
+    b.commit(token);
+    b.flush();
+
+    let token = b.next_token();
+    b.record(0x1a, token, 0x00, &[0x02]);
+    b.record(0x1c, token, 0x00, &[]);
+    b.commit(token);
+    b.flush();
+
+    let token = b.next_token();
+    b.commit(token);
+    b.flush();
+
+    // Display area: 640x480 at 0,0
+    let token = b.next_token();
+    b.record(0x12, token, 0x00, &[0xff, 0x00, 0x00, 0x00]);
+    b.record(0x13, token, 0x00, &[0xff, 0x00, 0x00, 0x00]);
+    b.record(0x10, token, 0x00, &[0x00, 0x00, 0x00, 0x00, 0x80, 0x02, 0xe0, 0x01]);
+    b.record(0x03, token, 0x00, &[]);
+    b.commit(token);
+    b.flush();
+
+    let token = b.next_token();
+    b.commit(token);
+    b.flush();
+
+    let token = b.next_token();
+    b.record(0x12, token, 0x00, &[0xff, 0xff, 0xff, 0xff]);
+    b.record(0x1d, token, 0x00, &[]);
+    b.write();
+
+    b.packets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::captureHelper::load_init_packets;
+    use crate::deviceProfileHelper::{PFP3N_HEADER, PFP7_HEADER};
+    use std::collections::HashMap;
+
+    // Host to device part of the init sequence in a capture: the 0xF0 reports of
+    // the upload and the backlight record. The device answers with 0xF0 0x01
+    // reports of its own sequence, those and the colour test page are dropped.
+    fn host_init_packets(packets: Vec<Vec<u8>>, header: [u8; 2]) -> Vec<Vec<u8>> {
+        let mut host = Vec::new();
+        let mut seq: Option<u8> = None;
+        for packet in packets {
+            let keep = match (packet[0], packet.get(1)) {
+                (0xF0, Some(0x00)) => true,
+                (0xF0, Some(0x01)) => seq.map(|s| s.wrapping_add(1)) == Some(packet[2]),
+                (0x02, Some(&h0)) => h0 == header[0],
+                _ => false,
+            };
+            if keep {
+                if packet[0] == 0xF0 {
+                    seq = Some(packet[2]);
+                }
+                host.push(packet);
+            }
+        }
+        host
+    }
+
+    // The vendor software takes its tokens from a clock, they're replaced by the
+    // order they are first used in. A commit still has to carry the token of its records.
+    fn number_tokens(mut packets: Vec<Vec<u8>>, header: [u8; 2]) -> Vec<Vec<u8>> {
+        // (packet, offset) of every byte of the record stream
+        let mut stream: Vec<(usize, usize)> = Vec::new();
+        for (i, packet) in packets.iter().enumerate() {
+            if packet[0] == 0xF0 && packet[1] == 0x00 {
+                stream.extend((4..4 + packet[3] as usize).map(|offset| (i, offset)));
+            }
+        }
+        let byte = |packets: &Vec<Vec<u8>>, at: usize| packets[stream[at].0][stream[at].1];
+        let mut numbers: HashMap<[u8; 4], u32> = HashMap::new();
+        let mut at = 0;
+        while at + 17 <= stream.len() {
+            assert_eq!([byte(&packets, at), byte(&packets, at + 1)], header, "record header at stream byte {}", at);
+            let token = [8, 9, 10, 11].map(|k| byte(&packets, at + k));
+            let next = numbers.len() as u32;
+            let number = *numbers.entry(token).or_insert(next);
+            for (k, b) in number.to_le_bytes().iter().enumerate() {
+                let (i, offset) = stream[at + 8 + k];
+                packets[i][offset] = *b;
+            }
+            let len = u32::from_le_bytes([13, 14, 15, 16].map(|k| byte(&packets, at + k))) as usize;
+            at += 17 + len;
+        }
+        assert_eq!(at, stream.len(), "record stream ends in the middle of a record");
+        packets
+    }
+
+    // Glyphs of the font chunks in a capture, every slot of the two fonts
+    fn captured_fonts(packets: &[Vec<u8>]) -> FontSet {
+        let stream: Vec<u8> = packets
+            .iter()
+            .filter(|p| p[0] == 0xF0 && p[1] == 0x00)
+            .flat_map(|p| p[4..4 + p[3] as usize].iter().copied())
+            .collect();
+        let mut data: HashMap<u32, Vec<u8>> = HashMap::new();
+        let mut at = 0;
+        while at + 17 <= stream.len() {
+            let len = u32::from_le_bytes(stream[at + 13..at + 17].try_into().unwrap()) as usize;
+            let payload = &stream[at + 17..at + 17 + len];
+            if stream[at + 4] == REC_FONT_CHUNK {
+                let font_id = u32::from_le_bytes(payload[0..4].try_into().unwrap());
+                data.entry(font_id).or_default().extend_from_slice(&payload[12..]);
+            }
+            at += 17 + len;
+        }
+        let glyphs = |font_id: u32| -> Vec<Glyph> {
+            data[&font_id]
+                .chunks(GLYPH_SIZE)
+                .map(|rec| Glyph {
+                    codepoint: u32::from_le_bytes(rec[0..4].try_into().unwrap()),
+                    rows: std::array::from_fn(|row| u32::from_be_bytes([0, rec[4 + row * 3], rec[5 + row * 3], rec[6 + row * 3]])),
+                })
+                .collect()
+        };
+        FontSet { large: glyphs(1), small: glyphs(2) }
+    }
+
+    #[test]
+    fn compiled_font_matches_vendor_capture() {
+        let captured = host_init_packets(load_init_packets("PFP3N.txt").unwrap(), PFP3N_HEADER);
+        let compiled = compile_init_packets(&load_font("font.txt").unwrap(), PFP3N_HEADER);
+        assert_eq!(compiled.len(), captured.len());
+        for (i, (ours, theirs)) in number_tokens(compiled, PFP3N_HEADER).iter().zip(number_tokens(captured, PFP3N_HEADER).iter()).enumerate() {
+            assert_eq!(hex::encode(ours), hex::encode(theirs), "packet {}", i);
+        }
+    }
+
+    // What the device gets per transaction (up to a 0xF0 0x01 flush): the records
+    // with their tokens blanked out, and any other report as it is
+    fn transactions(packets: &[Vec<u8>], header: [u8; 2]) -> Vec<Vec<u8>> {
+        let blank_tokens = |mut stream: Vec<u8>| {
+            let mut at = 0;
+            while at + 17 <= stream.len() {
+                assert_eq!(stream[at..at + 2], header, "record header at transaction byte {}", at);
+                stream[at + 8..at + 12].fill(0);
+                at += 17 + u32::from_le_bytes(stream[at + 13..at + 17].try_into().unwrap()) as usize;
+            }
+            stream
+        };
+        let mut done = Vec::new();
+        let mut stream: Vec<u8> = Vec::new();
+        for packet in packets {
+            match (packet[0], packet[1]) {
+                (0xF0, 0x00) => stream.extend_from_slice(&packet[4..4 + packet[3] as usize]),
+                (0xF0, _) => done.push(blank_tokens(std::mem::take(&mut stream))),
+                _ => done.push(packet.clone()),
+            }
+        }
+        done.push(blank_tokens(stream));
+        done
+    }
+
+    // The PFP 7 gets another font from the vendor software, so the upload is built
+    // from the glyphs of its capture. Its tokens and how the records are split into
+    // reports depend on the timing of the vendor software, only the records are compared.
+    #[test]
+    fn pfp7_upload_matches_its_capture() {
+        let captured = host_init_packets(load_init_packets("PFP7 wireshark.txt").unwrap(), PFP7_HEADER);
+        let compiled = compile_init_packets(&captured_fonts(&captured), PFP7_HEADER);
+        let (ours, theirs) = (transactions(&compiled, PFP7_HEADER), transactions(&captured, PFP7_HEADER));
+        assert_eq!(ours.len(), theirs.len());
+        for (i, (ours, theirs)) in ours.iter().zip(theirs.iter()).enumerate() {
+            assert_eq!(hex::encode(ours), hex::encode(theirs), "transaction {}", i);
+        }
+    }
+}
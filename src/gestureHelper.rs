@@ -0,0 +1,219 @@
+#![allow(non_snake_case)]
+// Turns key events into what the pages act on: presses, long presses, auto
+// repeat and chords. Which keys do what is set in main.rs.
+
+use std::collections::HashMap;
+use std::time::Instant;
+
+use crate::inputHelper::{KeyEvent, KeyEventKind};
+use crate::{CHORDS, LONG_PRESS_KEYS, LONG_PRESS_TIME, REPEAT_DELAY, REPEAT_INTERVAL, REPEAT_KEYS};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum GestureKind {
+    // on key down, on release for keys with a long press or that start a chord,
+    // and again and again while a repeating key is held
+    Press,
+    // held for LONG_PRESS_TIME, the key sends no Press then
+    LongPress,
+    // the second key went down while key was held, neither sends a Press
+    Chord(&'static str)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Gesture {
+    pub(crate) key: &'static str,
+    pub(crate) kind: GestureKind,
+    pub(crate) at: Instant
+}
+
+impl Gesture {
+    pub(crate) fn is_press(&self, key: &str) -> bool {
+        self.kind == GestureKind::Press && self.key == key
+    }
+}
+
+struct HeldKey {
+    since: Instant,
+    // the key was used up by a chord or its long press
+    consumed: bool,
+    next_repeat: Option<Instant>
+}
+
+// One per unit, the keys of two units don't form chords
+pub(crate) struct GestureDetector {
+    held: HashMap<&'static str, HeldKey>
+}
+
+fn starts_chord(key: &str) -> bool {
+    CHORDS.iter().any(|(first, _)| *first == key)
+}
+
+// Keys whose press can only be told apart once they are released
+fn is_deferred(key: &str) -> bool {
+    LONG_PRESS_KEYS.contains(&key) || starts_chord(key)
+}
+
+impl GestureDetector {
+    pub(crate) fn new() -> Self {
+        GestureDetector { held: HashMap::new() }
+    }
+
+    // Call every tick, also without events, long presses and repeats depend on the time
+    pub(crate) fn feed(&mut self, events: &[KeyEvent], now: Instant) -> Vec<Gesture> {
+        let mut gestures = Vec::new();
+        for event in events {
+            match event.kind {
+                KeyEventKind::Pressed => self.pressed(event, &mut gestures),
+                KeyEventKind::Released => self.released(event, &mut gestures),
+            }
+        }
+
+        for (&key, held) in self.held.iter_mut() {
+            if held.consumed {
+                continue;
+            }
+            if LONG_PRESS_KEYS.contains(&key) && now.duration_since(held.since) >= LONG_PRESS_TIME {
+                held.consumed = true;
+                gestures.push(Gesture { key, kind: GestureKind::LongPress, at: held.since + LONG_PRESS_TIME });
+                continue;
+            }
+            while let Some(due) = held.next_repeat.filter(|due| *due <= now) {
+                gestures.push(Gesture { key, kind: GestureKind::Press, at: due });
+                held.next_repeat = Some(due + REPEAT_INTERVAL);
+            }
+        }
+        gestures
+    }
+
+    fn pressed(&mut self, event: &KeyEvent, gestures: &mut Vec<Gesture>) {
+        let chord = self.held.iter_mut().find(|(first, held)| {
+            !held.consumed && CHORDS.contains(&(**first, event.key))
+        });
+        if let Some((&first, held)) = chord {
+            held.consumed = true;
+            gestures.push(Gesture { key: first, kind: GestureKind::Chord(event.key), at: event.at });
+            self.held.insert(event.key, HeldKey { since: event.at, consumed: true, next_repeat: None });
+            return;
+        }
+
+        let next_repeat = REPEAT_KEYS.contains(&event.key).then(|| event.at + REPEAT_DELAY);
+        self.held.insert(event.key, HeldKey { since: event.at, consumed: false, next_repeat });
+        if !is_deferred(event.key) {
+            gestures.push(Gesture { key: event.key, kind: GestureKind::Press, at: event.at });
+        }
+    }
+
+    fn released(&mut self, event: &KeyEvent, gestures: &mut Vec<Gesture>) {
+        if let Some(held) = self.held.remove(event.key) {
+            if is_deferred(event.key) && !held.consumed {
+                gestures.push(Gesture { key: event.key, kind: GestureKind::Press, at: event.at });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    fn down(key: &'static str, at: Instant) -> KeyEvent {
+        KeyEvent { key, kind: KeyEventKind::Pressed, at }
+    }
+
+    fn up(key: &'static str, at: Instant) -> KeyEvent {
+        KeyEvent { key, kind: KeyEventKind::Released, at }
+    }
+
+    fn kinds(gestures: &[Gesture]) -> Vec<(&'static str, GestureKind)> {
+        gestures.iter().map(|g| (g.key, g.kind)).collect()
+    }
+
+    #[test]
+    fn plain_key_presses_on_key_down_only() {
+        let mut detector = GestureDetector::new();
+        let t = Instant::now();
+        assert_eq!(kinds(&detector.feed(&[down("A", t)], t)), [("A", GestureKind::Press)]);
+        assert!(detector.feed(&[], t + ms(2000)).is_empty());
+        assert!(detector.feed(&[up("A", t + ms(2100))], t + ms(2100)).is_empty());
+    }
+
+    #[test]
+    fn long_press_replaces_the_press() {
+        let mut detector = GestureDetector::new();
+        let t = Instant::now();
+        assert!(detector.feed(&[down("CLR", t)], t).is_empty());
+        assert!(detector.feed(&[], t + LONG_PRESS_TIME - ms(1)).is_empty());
+        let gestures = detector.feed(&[], t + LONG_PRESS_TIME + ms(40));
+        assert_eq!(kinds(&gestures), [("CLR", GestureKind::LongPress)]);
+        // dated when the key was held long enough, not when the tick noticed
+        assert_eq!(gestures[0].at, t + LONG_PRESS_TIME);
+        assert!(detector.feed(&[], t + LONG_PRESS_TIME * 3).is_empty());
+        assert!(detector.feed(&[up("CLR", t + LONG_PRESS_TIME * 3)], t + LONG_PRESS_TIME * 3).is_empty());
+    }
+
+    #[test]
+    fn short_press_of_a_long_press_key_fires_on_release() {
+        let mut detector = GestureDetector::new();
+        let t = Instant::now();
+        assert!(detector.feed(&[down("CLR", t)], t).is_empty());
+        let gestures = detector.feed(&[up("CLR", t + ms(200))], t + ms(210));
+        assert_eq!(kinds(&gestures), [("CLR", GestureKind::Press)]);
+        assert_eq!(gestures[0].at, t + ms(200));
+    }
+
+    #[test]
+    fn press_and_release_in_one_batch() {
+        let mut detector = GestureDetector::new();
+        let t = Instant::now();
+        let gestures = detector.feed(&[down("CLR", t), up("CLR", t + ms(30)), down("A", t + ms(40)), up("A", t + ms(50))], t + ms(60));
+        assert_eq!(kinds(&gestures), [("CLR", GestureKind::Press), ("A", GestureKind::Press)]);
+        assert!(detector.feed(&[], t + LONG_PRESS_TIME * 2).is_empty());
+    }
+
+    #[test]
+    fn repeat_starts_after_the_delay_and_keeps_its_interval() {
+        let mut detector = GestureDetector::new();
+        let t = Instant::now();
+        assert_eq!(kinds(&detector.feed(&[down("DEL", t)], t)), [("DEL", GestureKind::Press)]);
+        assert!(detector.feed(&[], t + REPEAT_DELAY - ms(1)).is_empty());
+
+        // a slow tick catches up on every repeat it missed, each at its own time
+        let now = t + REPEAT_DELAY + REPEAT_INTERVAL * 2 + ms(5);
+        let gestures = detector.feed(&[], now);
+        let at: Vec<Instant> = gestures.iter().map(|g| g.at).collect();
+        assert_eq!(at, [t + REPEAT_DELAY, t + REPEAT_DELAY + REPEAT_INTERVAL, t + REPEAT_DELAY + REPEAT_INTERVAL * 2]);
+        assert!(gestures.iter().all(|g| g.is_press("DEL")));
+
+        assert!(detector.feed(&[up("DEL", now)], now + REPEAT_INTERVAL * 4).is_empty());
+    }
+
+    #[test]
+    fn chord_consumes_both_keys() {
+        let mut detector = GestureDetector::new();
+        let t = Instant::now();
+        // MENU starts a chord, its press waits for the release
+        assert!(detector.feed(&[down("MENU", t)], t).is_empty());
+        let gestures = detector.feed(&[down("EXEC", t + ms(100))], t + ms(100));
+        assert_eq!(kinds(&gestures), [("MENU", GestureKind::Chord("EXEC"))]);
+        assert!(detector.feed(&[up("EXEC", t + ms(200)), up("MENU", t + ms(300))], t + ms(300)).is_empty());
+
+        // a plain MENU press afterwards is a press again
+        assert!(detector.feed(&[down("MENU", t + ms(400))], t + ms(400)).is_empty());
+        assert_eq!(kinds(&detector.feed(&[up("MENU", t + ms(450))], t + ms(450))), [("MENU", GestureKind::Press)]);
+    }
+
+    #[test]
+    fn second_key_alone_is_no_chord() {
+        let mut detector = GestureDetector::new();
+        let t = Instant::now();
+        // EXEC first, then MENU, is no chord
+        assert_eq!(kinds(&detector.feed(&[down("EXEC", t)], t)), [("EXEC", GestureKind::Press)]);
+        assert!(detector.feed(&[down("MENU", t + ms(50))], t + ms(50)).is_empty());
+        assert_eq!(kinds(&detector.feed(&[up("MENU", t + ms(100))], t + ms(100))), [("MENU", GestureKind::Press)]);
+    }
+}
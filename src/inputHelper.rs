@@ -1,76 +1,119 @@
 #![allow(non_snake_case)]
 #![allow(unused_parens)]
 
-use hidapi::{ HidApi, HidDevice };
-use std::collections::HashMap;
-use std::io::{self, Write};
-
-use std::sync::{LazyLock, Mutex, OnceLock, RwLock};
+use anyhow::Result;
+use std::collections::VecDeque;
+use std::sync::Mutex;
 use std::time::Instant;
 use std::{thread, time::Duration};
 
-use crate::types::PFP_KEYCODES;
+use crate::keyMapHelper::{KeyLayout, KEY_BITS};
+use crate::transportHelper::Transport;
+
+const RECONNECT_POLL: u64 = 100;
+// events nobody picked up, the oldest go first
+const MAX_PENDING_EVENTS: usize = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyEventKind {
+    Pressed,
+    Released
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct KeyEvent {
+    pub key: &'static str,
+    pub kind: KeyEventKind,
+    pub at: Instant
+}
 
-static PRESSED_BUTTONS: LazyLock<RwLock<[u8; 72]>> =
-    LazyLock::new(|| RwLock::new([0; 72]));
+// Character a key types: 0..9, A..Z, space, / and .
+pub fn key_char(key: &str) -> Option<char> {
+    if key == "SP" {
+        return Some(' ');
+    }
+    let mut chars = key.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_ascii_alphanumeric() || c == '/' || c == '.' => Some(c),
+        _ => None,
+    }
+}
+
+// Buttons of one unit. The reader thread turns the reports into key events,
+// the page code of the same unit takes them, so every attached CDU has its own.
+pub struct KeyState {
+    pressed: Mutex<[u8; KEY_BITS]>,
+    events: Mutex<VecDeque<KeyEvent>>,
+    keys: &'static KeyLayout
+}
 
 fn format_inputs(input_raw: &[u8]) -> Vec<u8> {
-    let raw_shortened: &[u8] = &input_raw[1..10];
+    // units with fewer keys send shorter reports
+    let raw_shortened: &[u8] = &input_raw[1..input_raw.len().min(1 + KEY_BITS / 8)];
     let mut out = Vec::with_capacity(raw_shortened.len() * 8);
     for &byte in raw_shortened {
         for bit in 0..8 {
-            out.push(((byte >> bit) & 1) as u8);
+            out.push((byte >> bit) & 1);
         }
     }
     out
 }
 
-fn update_pressed_buttons(formatted_inputs: Vec<u8>) {
-    let mut buttons = PRESSED_BUTTONS.write().expect("pressed_buttons poisoned");
-    buttons.copy_from_slice(&formatted_inputs);
-}
+impl KeyState {
+    pub fn new(keys: &'static KeyLayout) -> Self {
+        KeyState { pressed: Mutex::new([0; KEY_BITS]), events: Mutex::new(VecDeque::new()), keys }
+    }
 
-// returns the character of the button that is pressed
-// if multiple buttons are pressed returns the one furthest up in list
-// valid buttons are 0..9, A..Z, Space, /, .
-pub fn get_button_pressed_char() -> String{ let guard = PRESSED_BUTTONS.read().expect("pressed_buttons poisoned");
-    // numbers first
-    for i in 28..=37 {
-        if guard.get(i) == Some(&1) {
-            let ch= char::from_digit((i as u32) - 28, 10).unwrap();
-            return ch.to_string();
+    // Diffs the new state against the last one, every change becomes an event.
+    // Indices without a name in the key layout are tracked but don't make events.
+    fn update_pressed_buttons(&self, formatted_inputs: &[u8]) {
+        let at = Instant::now();
+        let mut buttons = self.pressed.lock().expect("pressed_buttons poisoned");
+        let mut events = self.events.lock().expect("key events poisoned");
+        for (index, (old, &new)) in buttons.iter_mut().zip(formatted_inputs).enumerate() {
+            if *old == new {
+                continue;
+            }
+            *old = new;
+            if let Some(key) = self.keys.name(index) {
+                let kind = if new == 1 { KeyEventKind::Pressed } else { KeyEventKind::Released };
+                if events.len() == MAX_PENDING_EVENTS {
+                    events.pop_front();
+                }
+                events.push_back(KeyEvent { key, kind, at });
+            }
         }
     }
-    if(guard.get(38)==Some(&1)){
-        return String::from(".")
-    }
-    if(guard.get(39)==Some(&1)){
-        return String::from("0")
-    }
-    for i in 41usize..=66 { if guard.get(i) == Some(&1) {
-        // Map 41 -> 'A', 42 -> 'B', ..., 66 -> 'Z'
-        let ch = (b'A' + (i as u8 - 41)) as char;
-            return ch.to_string();
-        }
+
+    pub fn release_all(&self) {
+        self.update_pressed_buttons(&[0; KEY_BITS]);
     }
-    if(guard.get(69)==Some(&1)){
-        return String::from("/")
+
+    pub fn has_button(&self, button: &str) -> bool{
+        self.keys.index(button).is_some()
     }
-    return String::from("");
-}
 
-pub fn is_button_pressed(button: &str) -> bool{
-    let guard = PRESSED_BUTTONS.read().expect("pressed_buttons poisoned");
+    // For keys that don't come from a unit, e.g. typed into the emulator.
+    // Returns false for names the unit doesn't have.
+    pub fn set_button(&self, button: &str, pressed: bool) -> bool{
+        let index = match self.keys.index(button) {
+            Some(i) => i,
+            None => return false,
+        };
+        let mut state = *self.pressed.lock().expect("pressed_buttons poisoned");
+        state[index] = pressed as u8;
+        self.update_pressed_buttons(&state);
+        true
+    }
 
-    let index = match PFP_KEYCODES.get(button) {
-        Some(&i) => i as usize,
-        None => return false, // unknown button
-    };
-    return guard.get(index).copied().unwrap_or(0) == 1;
+    // Everything that happened since the last call, oldest first
+    pub fn take_events(&self) -> Vec<KeyEvent> {
+        self.events.lock().expect("key events poisoned").drain(..).collect()
+    }
 }
 
 // copy sent to keep original reference in main
-pub fn poll_nonblocking(dev: &HidDevice) -> hidapi::HidResult<()> {
+pub fn poll_nonblocking<T: Transport + ?Sized>(dev: &T, key_state: &KeyState) -> Result<()> {
     dev.set_blocking_mode(false)?; // switch to non-blocking
     let mut buf = [0u8; 64];
     loop {
@@ -78,20 +121,98 @@ pub fn poll_nonblocking(dev: &HidDevice) -> hidapi::HidResult<()> {
             Ok(n) if n > 0 => {
                 let report = &buf[..n];
                 if report[0] == 1{
-                    update_pressed_buttons(format_inputs(report));
-                    // println!("{:?}", get_button_pressed_char());
+                    key_state.update_pressed_buttons(&format_inputs(report));
                 }
             }
             Ok(_) => {
                 // no data right now
                 thread::sleep(Duration::from_millis(5));
             }
-            Err(e) => {
-                // handle I/O error (disconnect, etc.)
-                eprintln!("hid read error: {e}");
+            Err(_) => {
+                // Disconnected, the transport reports it and reopens the device.
+                // Nothing is held down while the unit is gone.
+                key_state.release_all();
+                thread::sleep(Duration::from_millis(RECONNECT_POLL));
+            }
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controlReferenceHelper::load_control_references;
+    use crate::keyMapHelper::{key_layout, load_key_maps};
+    use crate::transportHelper::MockTransport;
+    use std::sync::Arc;
+
+    fn pfp_keys() -> KeyState {
+        load_control_references("controls").unwrap();
+        load_key_maps("keys").unwrap();
+        KeyState::new(key_layout("pfp").unwrap())
+    }
+
+    // Waits for the reader thread
+    fn wait_for_events(keys: &KeyState, count: usize) -> Vec<KeyEvent> {
+        let mut events = Vec::new();
+        for _ in 0..200 {
+            events.extend(keys.take_events());
+            if events.len() >= count {
                 break;
             }
+            thread::sleep(Duration::from_millis(5));
         }
+        events
     }
-    Ok(())
-}
\ No newline at end of file
+
+    #[test]
+    fn reader_turns_reports_into_events() {
+        let keys = Arc::new(pfp_keys());
+        let index = key_layout("pfp").unwrap().index("A").unwrap();
+        let mut report = [0u8; 64];
+        report[0] = 1;
+        report[1 + index / 8] = 1 << (index % 8);
+
+        let device = Arc::new(MockTransport::new());
+        device.set_blocking_mode(true).unwrap();
+        device.push_read(&report);
+        let (reader_device, reader_keys) = (device.clone(), keys.clone());
+        thread::spawn(move || poll_nonblocking(&reader_device, &reader_keys));
+
+        let events = wait_for_events(&keys, 1);
+        assert!(!device.is_blocking());
+        assert_eq!(events.len(), 1);
+        assert_eq!((events[0].key, events[0].kind), ("A", KeyEventKind::Pressed));
+
+        // pulled out with A still down
+        device.set_unplugged(true);
+        let events = wait_for_events(&keys, 1);
+        assert_eq!(events.len(), 1);
+        assert_eq!((events[0].key, events[0].kind), ("A", KeyEventKind::Released));
+    }
+
+    #[test]
+    fn mcdu_keys_go_past_the_pfp_report() {
+        pfp_keys();
+        let keys = KeyState::new(key_layout("mcdu").unwrap());
+        // CLR is bit 73, in the tenth byte
+        let mut report = [0u8; 11];
+        report[0] = 1;
+        report[10] = 0x02;
+        keys.update_pressed_buttons(&format_inputs(&report));
+        // a PFP report is 9 bytes of keys and doesn't reach it
+        keys.update_pressed_buttons(&format_inputs(&report[..10]));
+        let events: Vec<(&str, KeyEventKind)> = keys.take_events().iter().map(|e| (e.key, e.kind)).collect();
+        assert_eq!(events, [("CLR", KeyEventKind::Pressed)]);
+    }
+
+    #[test]
+    fn set_button_makes_edges_only() {
+        let keys = pfp_keys();
+        assert!(keys.set_button("L1", true));
+        assert!(keys.set_button("L1", true));
+        assert!(keys.set_button("L1", false));
+        assert!(!keys.set_button("NO SUCH KEY", true));
+        let kinds: Vec<KeyEventKind> = keys.take_events().iter().map(|e| e.kind).collect();
+        assert_eq!(kinds, [KeyEventKind::Pressed, KeyEventKind::Released]);
+    }
+}
@@ -0,0 +1,306 @@
+#![allow(non_snake_case)]
+// Key layouts of the units and what the keys do in each aircraft, loaded from
+// the keys directory at start up so keys can be rebound without recompiling.
+//   keys/devices/<layout>.json     key name -> bit in the HID report
+//   keys/aircraft/<aircraft>.json  key name -> DCS-BIOS control, the file name
+//                                  is the aircraft name DCS reports (A-10C_2)
+// Bindings are checked against the control reference when they are loaded,
+// and against the layout of a unit when the unit asks for its layout.
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use crate::controlReferenceHelper::{control, InputInterface};
+use crate::{LONG_PRESS_KEYS, REPEAT_KEYS};
+
+// bits in the first 12 bytes of a key report, the PFP uses 9 of them, the MCDU 10
+pub(crate) const KEY_BITS: usize = 96;
+// {seat} in a control name is replaced with the seat of the unit
+const SEATS: [&str; 2] = ["PLT", "CPG"];
+
+pub struct KeyLayout {
+    indices: HashMap<String, u8>,
+    names: Vec<Option<String>>
+}
+
+impl KeyLayout {
+    pub fn index(&self, name: &str) -> Option<usize> {
+        self.indices.get(name).map(|&i| i as usize)
+    }
+
+    pub fn name(&self, index: usize) -> Option<&str> {
+        self.names.get(index)?.as_deref()
+    }
+}
+
+// Control that follows the key, moved to held when the key goes down
+// and to released when it comes up
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct StateBinding {
+    pub(crate) control: String,
+    pub(crate) held: u32,
+    pub(crate) released: u32
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct LongPressBinding {
+    pub(crate) control: String,
+    #[serde(default = "one")]
+    pub(crate) presses: u32
+}
+
+fn one() -> u32 {
+    1
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BindingFile {
+    // control reference the controls are looked up in
+    module: String,
+    #[serde(default)]
+    buttons: HashMap<String, String>,
+    // push buttons held down in DCS as long as the key is, 1 while held, 0 after
+    #[serde(default)]
+    momentary: HashMap<String, String>,
+    #[serde(default)]
+    rockers: HashMap<String, StateBinding>,
+    #[serde(default)]
+    long_press: HashMap<String, LongPressBinding>
+}
+
+pub(crate) enum Binding {
+    // push button, the action of the control, pressed and released by us
+    Button(String),
+    // momentary buttons and rockers, pressed and released with the key
+    State(StateBinding),
+    // momentary buttons and rockers on a key in REPEAT_KEYS, moved to held and
+    // back by us on key down and on every repeat
+    Pulse(StateBinding)
+}
+
+pub(crate) struct AircraftBindings {
+    pub(crate) module: String,
+    pub(crate) press: HashMap<String, Binding>,
+    pub(crate) long_press: HashMap<String, LongPressBinding>
+}
+
+struct KeyMaps {
+    layouts: HashMap<String, KeyLayout>,
+    aircraft: HashMap<String, AircraftBindings>
+}
+
+static KEY_MAPS: OnceLock<KeyMaps> = OnceLock::new();
+
+fn file_stem(path: &Path) -> String {
+    path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+fn json_files(dir: &Path) -> Result<Vec<std::path::PathBuf>> {
+    let entries = fs::read_dir(dir).with_context(|| format!("Failed to open key map directory {}", dir.display()))?;
+    let mut paths = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) == Some("json") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+fn parse_layout(json: &str) -> Result<KeyLayout> {
+    let indices: HashMap<String, u8> = serde_json::from_str(json)?;
+    let mut names = vec![None; KEY_BITS];
+    for (name, &index) in &indices {
+        let slot = names
+            .get_mut(index as usize)
+            .ok_or_else(|| anyhow!("Key '{}' is bit {}, a report only has {}", name, index, KEY_BITS))?;
+        if let Some(other) = slot.replace(name.clone()) {
+            bail!("Keys '{}' and '{}' are both bit {}", other, name, index);
+        }
+    }
+    Ok(KeyLayout { indices, names })
+}
+
+// Control names with {seat} stand for one control per seat
+fn expand_seats(control: &str) -> Vec<String> {
+    if control.contains("{seat}") {
+        SEATS.iter().map(|seat| control.replace("{seat}", seat)).collect()
+    } else {
+        vec![control.to_string()]
+    }
+}
+
+fn check_action(module: &str, identifier: &str) -> Result<()> {
+    for identifier in expand_seats(identifier) {
+        let control = control(module, &identifier).ok_or_else(|| anyhow!("'{}' is not a control of {}", identifier, module))?;
+        if !control.inputs.iter().any(|i| matches!(i, InputInterface::Action(_))) {
+            bail!("'{}' can't be pressed, it has no action input", identifier);
+        }
+    }
+    Ok(())
+}
+
+fn check_states(module: &str, binding: &StateBinding) -> Result<()> {
+    for identifier in expand_seats(&binding.control) {
+        let control = control(module, &identifier).ok_or_else(|| anyhow!("'{}' is not a control of {}", identifier, module))?;
+        let max_value = control
+            .inputs
+            .iter()
+            .find_map(|i| match i {
+                InputInterface::SetState(max_value) => Some(*max_value),
+                _ => None,
+            })
+            .ok_or_else(|| anyhow!("'{}' can't be set to a position", identifier))?;
+        for state in [binding.held, binding.released] {
+            if state > max_value {
+                bail!("'{}' goes from 0 to {}, {} is out of range", identifier, max_value, state);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn parse_bindings(json: &str) -> Result<AircraftBindings> {
+    let file: BindingFile = serde_json::from_str(json)?;
+
+    let mut press = HashMap::new();
+    for (key, identifier) in file.buttons {
+        check_action(&file.module, &identifier)?;
+        press.insert(key, Binding::Button(identifier));
+    }
+    let momentary = file.momentary.into_iter().map(|(key, control)| (key, StateBinding { control, held: 1, released: 0 }));
+    for (key, binding) in momentary.chain(file.rockers) {
+        check_states(&file.module, &binding)?;
+        let binding = if REPEAT_KEYS.contains(&key.as_str()) { Binding::Pulse(binding) } else { Binding::State(binding) };
+        if press.insert(key.clone(), binding).is_some() {
+            bail!("Key '{}' is bound more than once", key);
+        }
+    }
+    for (key, binding) in &file.long_press {
+        check_action(&file.module, &binding.control)?;
+        // other keys act on key down, they never get held long enough
+        if !LONG_PRESS_KEYS.contains(&key.as_str()) {
+            bail!("Key '{}' has a long press binding but isn't in LONG_PRESS_KEYS", key);
+        }
+        // DCS sees how long it is held already
+        if let Some(Binding::State(_)) = press.get(key) {
+            bail!("Key '{}' follows the key in DCS, it can't have a long press binding", key);
+        }
+    }
+    Ok(AircraftBindings { module: file.module, press, long_press: file.long_press })
+}
+
+// Loads all layouts and bindings, returns their names. Needs the control
+// reference, only the first call has an effect.
+pub(crate) fn load_key_maps(dir: &str) -> Result<(Vec<String>, Vec<String>)> {
+    let dir = Path::new(dir);
+    let mut layouts = HashMap::new();
+    for path in json_files(&dir.join("devices"))? {
+        let json = fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let layout = parse_layout(&json).with_context(|| format!("Invalid key layout {}", path.display()))?;
+        layouts.insert(file_stem(&path), layout);
+    }
+    let mut aircraft = HashMap::new();
+    for path in json_files(&dir.join("aircraft"))? {
+        let json = fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let bindings = parse_bindings(&json).with_context(|| format!("Invalid key bindings {}", path.display()))?;
+        aircraft.insert(file_stem(&path), bindings);
+    }
+
+    let mut layout_names: Vec<String> = layouts.keys().cloned().collect();
+    let mut aircraft_names: Vec<String> = aircraft.keys().cloned().collect();
+    layout_names.sort();
+    aircraft_names.sort();
+    let _ = KEY_MAPS.set(KeyMaps { layouts, aircraft });
+    Ok((layout_names, aircraft_names))
+}
+
+// A unit shows the page of whatever aircraft is in DCS, so every key of every
+// binding file has to be on it
+fn check_keys(aircraft: &HashMap<String, AircraftBindings>, layout_name: &str, layout: &KeyLayout) -> Result<()> {
+    let mut names: Vec<&String> = aircraft.keys().collect();
+    names.sort();
+    for name in names {
+        let bindings = &aircraft[name];
+        let mut keys: Vec<&String> = bindings.press.keys().chain(bindings.long_press.keys()).collect();
+        keys.sort();
+        if let Some(key) = keys.into_iter().find(|key| layout.index(key).is_none()) {
+            bail!("keys/aircraft/{}.json binds '{}', the {} layout has no such key", name, key, layout_name);
+        }
+    }
+    Ok(())
+}
+
+// Layout of a unit, fails if a binding file uses a key the unit doesn't have
+pub(crate) fn key_layout(name: &str) -> Result<&'static KeyLayout> {
+    let maps = KEY_MAPS.get().ok_or_else(|| anyhow!("Key maps aren't loaded"))?;
+    let layout = maps.layouts.get(name).ok_or_else(|| anyhow!("No key layout keys/devices/{}.json", name))?;
+    check_keys(&maps.aircraft, name, layout)?;
+    Ok(layout)
+}
+
+// None for aircraft without a binding file, their keys do nothing
+pub(crate) fn aircraft_bindings(aircraft: &str) -> Option<&'static AircraftBindings> {
+    KEY_MAPS.get()?.aircraft.get(aircraft)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controlReferenceHelper::load_control_references;
+    use crate::deviceProfileHelper::PROFILES;
+
+    const LAYOUT: &str = r#"{ "A": 0, "L1": 1, "CLR": 2 }"#;
+
+    fn bindings(json: &str) -> HashMap<String, AircraftBindings> {
+        load_control_references("controls").unwrap();
+        HashMap::from([("A-10C_2".to_string(), parse_bindings(json).unwrap())])
+    }
+
+    #[test]
+    fn bindings_are_checked_against_the_units_layout() {
+        let layout = parse_layout(LAYOUT).unwrap();
+        let aircraft = bindings(r#"{ "module": "A-10C", "buttons": { "A": "CDU_A" },
+            "rockers": { "L1": { "control": "CDU_SCROLL", "held": 0, "released": 1 } } }"#);
+        check_keys(&aircraft, "small", &layout).unwrap();
+
+        // a key another layout may have, this one doesn't
+        let aircraft = bindings(r#"{ "module": "A-10C", "buttons": { "A": "CDU_A", "B": "CDU_B" } }"#);
+        let error = check_keys(&aircraft, "small", &layout).unwrap_err().to_string();
+        assert_eq!(error, "keys/aircraft/A-10C_2.json binds 'B', the small layout has no such key");
+    }
+
+    #[test]
+    fn long_press_keys_are_checked_too() {
+        let layout = parse_layout(r#"{ "A": 0 }"#).unwrap();
+        let aircraft = bindings(r#"{ "module": "A-10C", "long_press": { "CLR": { "control": "CDU_CLR", "presses": 2 } } }"#);
+        assert!(check_keys(&aircraft, "small", &layout).is_err());
+    }
+
+    #[test]
+    fn bound_controls_have_to_fit() {
+        load_control_references("controls").unwrap();
+        assert!(parse_bindings(r#"{ "module": "A-10C", "buttons": { "A": "NOT_A_CONTROL" } }"#).is_err());
+        // CDU_SCROLL goes from 0 to 2
+        assert!(parse_bindings(r#"{ "module": "A-10C", "rockers": { "L1": { "control": "CDU_SCROLL", "held": 3, "released": 1 } } }"#).is_err());
+        let twice = r#"{ "module": "A-10C", "buttons": { "A": "CDU_A" }, "rockers": { "A": { "control": "CDU_SCROLL", "held": 0, "released": 1 } } }"#;
+        assert_eq!(parse_bindings(twice).err().unwrap().to_string(), "Key 'A' is bound more than once");
+    }
+
+    #[test]
+    fn shipped_bindings_fit_every_unit() {
+        load_control_references("controls").unwrap();
+        load_key_maps("keys").unwrap();
+        for profile in &PROFILES {
+            key_layout(profile.keys).unwrap();
+        }
+    }
+}
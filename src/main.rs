@@ -1,161 +1,321 @@
 #![allow(non_snake_case)]
-#![allow(unused_imports)]
 #![allow(unused_parens)]
 mod writeHelper;
 mod dcsBiosHelper;
 mod moduleDataProcessorHelper;
 mod inputHelper;
 mod searchModeHelper;
+mod screenHelper;
+mod fontHelper;
+mod captureHelper;
+mod emulatorHelper;
+mod transportHelper;
+mod deviceProfileHelper;
+mod cduHelper;
+mod streamParserHelper;
+mod commandHelper;
+mod controlReferenceHelper;
+mod outputHelper;
+mod subscriptionHelper;
+mod memoryImageHelper;
+mod exportReplayHelper;
+mod biosServerHelper;
+mod mockBiosHelper;
+mod gestureHelper;
+mod keyMapHelper;
 pub(crate) mod types;
 
-use crate::types::{TextBlock};
-use crate::writeHelper::{send_init_from_file, send_text_to_disp};
-use crate::dcsBiosHelper::{read_stream, get_map};
-use crate::moduleDataProcessorHelper::{get_A10C2_text, get_AH64D_text, get_AV8B_text, get_CH47F_text, get_module_name, handle_A10C2_input, handle_AH64D_input};
-use crate::inputHelper::{is_button_pressed, poll_nonblocking};
-use crate::searchModeHelper::{get_search_mode_disp};
+use crate::screenHelper::{SCREEN_COLS, SCREEN_ROWS};
+use crate::captureHelper::load_init_packets;
+use crate::transportHelper::{Opener, ReconnectingTransport, Recorder, Transport};
+use crate::emulatorHelper::{render_ansi, render_plain, Emulator};
+use crate::fontHelper::{compile_init_packets, load_font};
+use crate::dcsBiosHelper::{ports, read_stream, set_connection, set_export_recorder, set_ports, BiosConnection, BiosPorts};
+use crate::exportReplayHelper::{load_recording, serve_recording, ExportRecorder};
+use crate::mockBiosHelper::{start_mock_bios, MockBios};
+use crate::inputHelper::{poll_nonblocking, KeyState};
+use crate::deviceProfileHelper::{detect_devices, DetectedDevice, InitSequence, UnitLocator};
+use crate::cduHelper::{Cdu, Role};
+use crate::controlReferenceHelper::load_control_references;
+use crate::keyMapHelper::{key_layout, load_key_maps};
 
 use anyhow::{ anyhow, Context, Result };
-use hidapi::{ HidApi, HidDevice };
+use hidapi::{ HidApi };
+use std::sync::{Arc, Mutex};
 use std::thread;
-use std::collections::HashMap;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
 
-const VID: u16 = 0x4098;
-const PID: u16 = 0xbb35; // PFP 3N
-// const PID: u16 = 0xbb37; // PFP 7
 const WRITE_DELAY_SHORT: f32 = 0.005;
 const WRITE_DELAY_LONG: f32 = 0.01;
 const TOGGLE_DELAY: u64 = 100;
 const THREAD_SLEEP: u64 = 10;
-const INIT_PATH: &str = "output4.txt";
+// Set to a captured init sequence to replay it instead of the one of the device profile.
+// Bare hex lines, device.write([...]) dumps and Wireshark hex dumps all work.
+const INIT_PATH: Option<&str> = None;
 const MANUAL_BUTTON_MAPPING: bool = false;
+// Key gestures. Long press keys do something else when held (hold CLR to clear
+// the whole scratchpad in DCS), repeat keys fire again and again while held,
+// chords are a held key plus a second one (MENU+EXEC uploads the font again).
+const LONG_PRESS_TIME: Duration = Duration::from_millis(600);
+const REPEAT_DELAY: Duration = Duration::from_millis(500);
+// longer than a button press to DCS, or the repeats get swallowed
+const REPEAT_INTERVAL: Duration = Duration::from_millis(150);
+const LONG_PRESS_KEYS: &[&str] = &["CLR"];
+// a rocker or momentary button on a repeat key is pushed and let go again for every repeat
+const REPEAT_KEYS: &[&str] = &["DEL", "PREV PAGE", "NEXT PAGE"];
+const CHORDS: &[(&str, &str)] = &[("MENU", "EXEC")];
+// Role of every attached unit, in the order they are listed on start up.
+// Units without an entry show the page of the aircraft (Role::Auto).
+// e.g. &[Role::Ah64dPilot, Role::Ah64dCpg] for both Apache seats, or
+// &[Role::Auto, Role::Reference] for the A-10 CDU plus the RWR page.
+// --roles auto,reference overrides this without recompiling.
+const ROLES: &[Role] = &[];
+// How to reach DCS-BIOS, --tcp or --udp overrides it
+const BIOS_CONNECTION: BiosConnection = BiosConnection::Tcp;
+// How long a key typed into the emulator is held, shorter than a button press
+// to DCS so it sends exactly one
+const VIRTUAL_PRESS: Duration = Duration::from_millis(50);
+// commands of the mock shown under the emulated display
+const MOCK_COMMANDS_SHOWN: usize = 8;
+// DCS-BIOS control reference JSON files, one per module
+const CONTROLS_PATH: &str = "controls";
+// Key layouts of the units (devices/) and key bindings per aircraft (aircraft/)
+const KEYS_PATH: &str = "keys";
 
-pub fn find_device() -> Result<HidDevice> {
-    let hid_api = HidApi::new().context("Failed to initialize HID API")?;
+// Key names typed into the terminal are pressed on the emulated unit, one per
+// line: "A", "NEXT PAGE", "CLR*1000" holds CLR for a second, "MENU+EXEC"
+// presses MENU and then EXEC while MENU is held
+fn read_virtual_keys(keys: Arc<KeyState>) {
+    for line in std::io::stdin().lines() {
+        let Ok(line) = line else { break };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let held = line.rsplit_once('*').and_then(|(names, ms)| Some((names.trim(), ms.trim().parse::<u64>().ok()?)));
+        let (names, hold) = match held {
+            Some((names, ms)) => (names, Duration::from_millis(ms)),
+            None => (line, VIRTUAL_PRESS),
+        };
+        // "+-" is a key of its own
+        let names: Vec<&str> = if keys.has_button(names) { vec![names] } else { names.split('+').map(str::trim).collect() };
+        if let Some(unknown) = names.iter().find(|n| !keys.has_button(n)) {
+            eprintln!("PFP_WRITER: No key named '{}'", unknown);
+            continue;
+        }
+        for name in &names {
+            keys.set_button(name, true);
+            thread::sleep(VIRTUAL_PRESS);
+        }
+        thread::sleep(hold);
+        for name in names.iter().rev() {
+            keys.set_button(name, false);
+        }
+    }
+}
 
-    // Prefer opening by product string; fall back to the first matching VID/PID.
-    let dev_info = hid_api
-        .device_list()
-        .find(|d|
-            d.vendor_id() == VID &&
-            d.product_id() == PID &&
-            d.product_string().unwrap_or("").contains("WINWING PFP-3N-CAPTAIN")
-        )
-        .or_else(|| {
-            hid_api
-                .device_list()
-                .find(|d| d.vendor_id() == VID && d.product_id() == PID)
-        })
-        .ok_or_else(|| anyhow!("PFP_WRITER: No HID interfaces with VID={:04X} PID={:04X} found by hidapi", VID, PID))?;
+// Last commands the mock got, with the time since the one before
+fn format_mock_commands(mock: &MockBios) -> String {
+    let commands = mock.commands();
+    let first = commands.len().saturating_sub(MOCK_COMMANDS_SHOWN);
+    let mut text = String::from("\nDCS-BIOS commands:\n");
+    for (i, command) in commands.iter().enumerate().skip(first) {
+        let since_last = match i.checked_sub(1).and_then(|p| commands.get(p)) {
+            Some(previous) => command.at.saturating_sub(previous.at),
+            None => Duration::ZERO,
+        };
+        text += &format!("+{:.3} (+{:.3}) {}\n", command.at.as_secs_f64(), since_last.as_secs_f64(), command.line);
+    }
+    text
+}
 
-    let path = dev_info.path();
+// Runs the bridge without a PFP attached, the packets that would go to the
+// display are decoded by the emulator and drawn into the terminal instead.
+fn run_emulated(role: Role, plain: bool, mock: Option<Arc<MockBios>>) -> Result<()> {
+    let emulator = Arc::new(Mutex::new(Emulator::new()));
+    let keys = Arc::new(KeyState::new(key_layout("pfp")?));
+    let virtual_keys = keys.clone();
+    thread::spawn(move || read_virtual_keys(virtual_keys));
+    let mut cdu = Cdu::new(role, Box::new(emulator.clone()), keys, Vec::new());
+    let mut reports = 0;
+    let mut commands = 0;
+    loop {
+        cdu.tick();
 
-    let device = hid_api
-        .open_path(path)
-        .with_context(|| format!("PFP_WRITER: Failed to open HID path {}", path.to_string_lossy()))?;
+        let guard = match emulator.lock() {
+            Ok(e) => e,
+            Err(p) => p.into_inner(),
+        };
+        let command_count = mock.as_ref().map_or(0, |m| m.commands().len());
+        if guard.reports() != reports || command_count != commands {
+            reports = guard.reports();
+            commands = command_count;
+            let rendered = if plain { render_plain(guard.screen()) } else { render_ansi(guard.screen()) };
+            let captured = mock.as_deref().map(format_mock_commands).unwrap_or_default();
+            // cursor home + clear, so the grid is redrawn in place
+            print!("\x1b[H\x1b[2J{}{}", rendered, captured);
+        }
+        drop(guard);
+        thread::sleep(Duration::from_millis(THREAD_SLEEP));
+    }
+}
 
-    Ok(device)
+fn parse_roles(args: &[String]) -> Result<Vec<Role>> {
+    match args.iter().position(|a| a == "--roles") {
+        Some(i) => {
+            let list = args.get(i + 1).ok_or_else(|| anyhow!("--roles needs a comma separated list"))?;
+            list.split(',').map(Role::parse).collect()
+        }
+        None => Ok(ROLES.to_vec()),
+    }
 }
 
+// Value after a flag, e.g. the file of --record-hid <file>
+fn flag_value<'a>(args: &'a [String], flag: &str) -> Result<Option<&'a str>> {
+    match args.iter().position(|a| a == flag) {
+        Some(i) => args.get(i + 1).map(|v| Some(v.as_str())).ok_or_else(|| anyhow!("{} needs a value", flag)),
+        None => Ok(None),
+    }
+}
 
-fn main() -> Result<()> {
-    let write_device = find_device()?;
-    let read_device = find_device()?;
+// Init packets of a unit, loaded once so a broken font or capture shows up on start up
+fn load_init_sequence(init: InitSequence) -> Result<Vec<Vec<u8>>> {
+    match init {
+        InitSequence::Capture(init_path) => load_init_packets(init_path),
+        InitSequence::Font(font_path, header) => Ok(compile_init_packets(&load_font(font_path)?, header)),
+    }
+}
 
-    // Sends init package from file
-    send_init_from_file(&write_device, INIT_PATH, WRITE_DELAY_SHORT);
-    println!("PFP_WRITER: Sent init packets to {:04X}:{:04X} from {}", VID, PID, INIT_PATH);
+// Opens the unit and starts its input and display threads, the display thread
+// uploads fonts and palette before the first page
+fn start_cdu(detected: &DetectedDevice, index: usize, role: Role, recorder: Option<&Recorder>) -> Result<thread::JoinHandle<()>> {
+    let profile = detected.profile;
+    let (vid, pid) = (detected.info.vendor_id(), detected.info.product_id());
+    let name = format!("{} #{}", profile.name, index);
+    println!("PFP_WRITER: Found {} ({:04X}:{:04X}, {}x{}), role {:?}", name, vid, pid, profile.cols, profile.rows, role);
+    if (profile.cols, profile.rows) != (SCREEN_COLS, SCREEN_ROWS) {
+        return Err(anyhow!("{} has a {}x{} display, only {}x{} is supported", profile.name, profile.cols, profile.rows, SCREEN_COLS, SCREEN_ROWS));
+    }
 
-    // let test = vec![TextBlock{ text: "Auvwxyz".to_string(), bg: "black".to_string(), fg: "white".to_string() }];
-    // send_text_to_disp(&write_device, WRITE_DELAY_S,&test);
+    let init = INIT_PATH.map(InitSequence::Capture).unwrap_or(profile.init);
+    let init_packets = load_init_sequence(init)?;
+    println!("PFP_WRITER: {} init packets for {} from {:?}", init_packets.len(), name, init);
 
-    // Spawn background thread for DCS_Bios
-    thread::spawn(|| {
-        if let Err(e) = read_stream() {
-            eprintln!("read_stream error: {}", e);
-        }
+    // Used again whenever the unit has to be reopened after a disconnect
+    let locator = UnitLocator::new(detected);
+    let recorder = recorder.cloned();
+    let open: Opener = Arc::new(move || {
+        let device = locator.open()?;
+        Ok(match &recorder {
+            Some(recorder) => Box::new(recorder.wrap(device)) as Box<dyn Transport>,
+            None => Box::new(device),
+        })
     });
+    // One handle for both directions, so a disconnect noticed by the reader also
+    // makes the display thread initialise the unit again once it is back
+    let device = Arc::new(ReconnectingTransport::new(&name, open)?);
+    let read_device = device.clone();
 
     // Spawn background thread for reading device inputs
+    let keys = Arc::new(KeyState::new(key_layout(profile.keys)?));
+    let reader_keys = keys.clone();
+    let reader_name = name.clone();
     thread::spawn(move || {
-        if let Err(e) = poll_nonblocking(&read_device) {
-            eprintln!("PFP_WRITER: HID reader error: {e}");
+        if let Err(e) = poll_nonblocking(&read_device, &reader_keys) {
+            eprintln!("PFP_WRITER: {} HID reader error: {e}", reader_name);
         }
     });
 
-    // variables required for switching to search mode
-    let mut search_mode = false;
-    let mut last_toggle = Instant::now() - Duration::from_millis(TOGGLE_DELAY);
-    loop {
-        // msb = mode switch button
-        let msb_pressed = is_button_pressed("MENU");
-        if (msb_pressed
-            && last_toggle.elapsed() >= Duration::from_millis(TOGGLE_DELAY)) {
-                search_mode = !search_mode;
-                last_toggle = Instant::now();
-        }
+    let cdu = Cdu::new(role, Box::new(device), keys, init_packets);
+    Ok(thread::spawn(move || cdu.run()))
+}
 
-        if(search_mode && !MANUAL_BUTTON_MAPPING){
-            let mut res: Vec<TextBlock> = Vec::new();
-            res.push(
-                TextBlock {
-                    text: String::from("Search Mode: "),
-                    bg: (String::from("black")),
-                    fg: (String::from("green"))
-                }
-            );
-
-            let search_mode_disp = get_search_mode_disp();
-            send_text_to_disp(&write_device, WRITE_DELAY_LONG, &search_mode_disp);
-        }
+fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().collect();
+    let roles = parse_roles(&args)?;
+
+    let connection = if args.iter().any(|a| a == "--udp") {
+        BiosConnection::Udp
+    } else if args.iter().any(|a| a == "--tcp") {
+        BiosConnection::Tcp
+    } else {
+        BIOS_CONNECTION
+    };
+    set_connection(connection);
+    // --bios-port <port> for a DCS-BIOS (or --mock-bios/--replay-bios) on another port, 0 picks a free one for a local server
+    if let Some(port) = flag_value(&args, "--bios-port")? {
+        let port: u16 = port.parse().map_err(|_| anyhow!("--bios-port needs a port number"))?;
+        set_ports(BiosPorts { tcp: port, command: port, ..ports() });
+    }
 
+    let modules = load_control_references(CONTROLS_PATH)?;
+    println!("PFP_WRITER: Control references for {}", modules.join(", "));
+    let (layouts, aircraft) = load_key_maps(KEYS_PATH)?;
+    println!("PFP_WRITER: Key layouts {}, key bindings for {}", layouts.join(", "), aircraft.join(", "));
 
-        // normal display mode
-        if(!search_mode){
-            let map_arc = get_map(); // clone Arc so it lives long enough
-            let snapshot: HashMap<u16, [u8; 2]> = {
-                let guard = match map_arc.lock() {
-                    Ok(g) => g,
-                    Err(p) => p.into_inner(),
-                };
-                guard.clone() // clones the whole HashMap
-            };
-
-            // yes, I know this gets called every 10ms
-            // yes, I know its not performant
-            let module_name = get_module_name(&snapshot);
-            // if(!module_name.starts_with("?")){
-            //     println!("Module: {:}", module_name);
-            // }
-
-            let mut res: Vec<TextBlock> = Vec::new();
-            if module_name.starts_with("A-10C_2"){
-                res = get_A10C2_text(&snapshot);
-                if (!MANUAL_BUTTON_MAPPING){
-                    handle_A10C2_input()
-                };
-            }
-            else if(module_name.starts_with("AV8B")){
-                res = get_AV8B_text(&snapshot);
-            }
-            else if(module_name.starts_with("CH-47F")){
-                res = get_CH47F_text(&snapshot);
-            }
-            else if(module_name.starts_with("AH-64D_BLK_II")){
-                res = get_AH64D_text(&snapshot);
-                if (!MANUAL_BUTTON_MAPPING){
-                    handle_AH64D_input(&snapshot)
-                };
-            }
-
-            // send_text_to_disp(&write_device, 0.01, &res);
-            send_text_to_disp(&write_device, WRITE_DELAY_LONG,&res);
+    // --record-bios <file> saves the export stream, --replay-bios <file> plays one
+    // back in place of DCS (--replay-speed 4 for four times as fast)
+    if let Some(path) = flag_value(&args, "--record-bios")? {
+        println!("PFP_WRITER: Recording the DCS-BIOS export to {}", path);
+        set_export_recorder(ExportRecorder::create(path)?);
+    }
+    if let Some(path) = flag_value(&args, "--replay-bios")? {
+        let speed = match flag_value(&args, "--replay-speed")? {
+            Some(speed) => speed.parse::<f64>().ok().filter(|s| *s > 0.0).ok_or_else(|| anyhow!("--replay-speed needs a number above 0"))?,
+            None => 1.0,
+        };
+        set_ports(serve_recording(load_recording(path)?, speed, connection)?);
+    }
+    // --mock-bios <script> exports a fixed memory image and keeps the commands it gets
+    let mock = match flag_value(&args, "--mock-bios")? {
+        Some(_) if args.iter().any(|a| a == "--replay-bios") => return Err(anyhow!("--mock-bios and --replay-bios can't be used together")),
+        Some(path) => Some(start_mock_bios(path, connection, ports(), !args.iter().any(|a| a == "--emulate"))?),
+        None => None,
+    };
+    // the bridge follows the mock to the port it got
+    if let Some(mock) = &mock {
+        set_ports(mock.ports());
+    }
+
+    // Spawn background thread for DCS_Bios, it reconnects on its own
+    thread::spawn(read_stream);
+
+    if args.iter().any(|a| a == "--emulate") {
+        let role = roles.first().copied().unwrap_or(Role::Auto);
+        return run_emulated(role, args.iter().any(|a| a == "--plain"), mock);
+    }
+
+    let hid_api = HidApi::new().context("Failed to initialize HID API")?;
+    let devices = detect_devices(&hid_api);
+    if devices.is_empty() {
+        return Err(anyhow!("PFP_WRITER: No supported WinWing unit found by hidapi"));
+    }
+
+    // --record-hid <file> logs every report going to and coming from the devices
+    let recorder = match flag_value(&args, "--record-hid")? {
+        Some(path) => {
+            println!("PFP_WRITER: Recording HID traffic to {}", path);
+            Some(Recorder::create(path)?)
         }
+        None => None,
+    };
 
-        thread::sleep(Duration::from_millis(THREAD_SLEEP));
+    let mut pipelines = Vec::new();
+    for (i, detected) in devices.iter().enumerate() {
+        let role = roles.get(i).copied().unwrap_or(Role::Auto);
+        // one unit that fails doesn't take the others down
+        match start_cdu(detected, i, role, recorder.as_ref()) {
+            Ok(pipeline) => pipelines.push(pipeline),
+            Err(e) => eprintln!("PFP_WRITER: {} #{} not started: {:#}", detected.profile.name, i, e),
+        }
+    }
+    if pipelines.is_empty() {
+        return Err(anyhow!("PFP_WRITER: None of the attached units could be started"));
     }
 
-    // Ok(())
+    for pipeline in pipelines {
+        let _ = pipeline.join();
+    }
+    Ok(())
 }
@@ -0,0 +1,104 @@
+#![allow(non_snake_case)]
+// The DCS-BIOS export memory as one flat 64K image. The stream reader writes
+// into its own copy and publishes it at the end of every frame, pages take the
+// last published frame, which is only an Arc clone.
+
+use std::mem;
+use std::sync::{Arc, LazyLock, RwLock};
+
+const MEMORY_SIZE: usize = 0x10000;
+
+pub(crate) struct MemoryImage {
+    bytes: Box<[u8]>,
+    // counts published frames, equal generations hold the same data
+    generation: u64
+}
+
+impl MemoryImage {
+    pub(crate) fn new() -> Self {
+        MemoryImage { bytes: vec![0; MEMORY_SIZE].into_boxed_slice(), generation: 0 }
+    }
+
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    // Image with only the given words set, for rendering pages without a stream
+    #[cfg(test)]
+    pub(crate) fn from_words(words: &[(u16, [u8; 2])]) -> Self {
+        let mut image = MemoryImage::new();
+        for (address, word) in words {
+            let address = *address as usize;
+            image.bytes[address..address + 2].copy_from_slice(word);
+        }
+        image
+    }
+
+    // Little endian like the export, the byte past 0xffff reads as 0
+    pub(crate) fn word(&self, address: u16) -> u16 {
+        let address = address as usize;
+        let high = self.bytes.get(address + 1).copied().unwrap_or(0);
+        u16::from_le_bytes([self.bytes[address], high])
+    }
+
+    // Up to len bytes from address, shorter at the end of the memory
+    pub(crate) fn bytes(&self, address: u16, len: u16) -> &[u8] {
+        let start = address as usize;
+        let end = (start + len as usize).min(MEMORY_SIZE);
+        &self.bytes[start..end]
+    }
+}
+
+static PUBLISHED: LazyLock<RwLock<Arc<MemoryImage>>> = LazyLock::new(|| RwLock::new(Arc::new(MemoryImage::new())));
+
+// Last finished frame, all zeros while DCS-BIOS isn't connected
+pub(crate) fn snapshot() -> Arc<MemoryImage> {
+    PUBLISHED.read().unwrap_or_else(|p| p.into_inner()).clone()
+}
+
+// Owned by the stream reader
+pub(crate) struct MemoryWriter {
+    working: MemoryImage,
+    // the frame published before the current one, reused once no page holds it
+    spare: Option<Arc<MemoryImage>>
+}
+
+impl MemoryWriter {
+    pub(crate) fn new() -> Self {
+        MemoryWriter { working: MemoryImage::new(), spare: None }
+    }
+
+    // true if the word changed
+    pub(crate) fn write(&mut self, address: u16, word: [u8; 2]) -> bool {
+        // words are aligned, 0xffff only comes from a broken stream
+        if address == 0xffff {
+            return false;
+        }
+        let address = address as usize;
+        let cell = &mut self.working.bytes[address..address + 2];
+        if cell == word {
+            return false;
+        }
+        cell.copy_from_slice(&word);
+        true
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.working.bytes.fill(0);
+    }
+
+    // Hands the working image to the pages as the next frame
+    pub(crate) fn publish(&mut self) {
+        self.working.generation += 1;
+        let mut next = match self.spare.take() {
+            Some(spare) if Arc::strong_count(&spare) == 1 => spare,
+            _ => Arc::new(MemoryImage::new()),
+        };
+        if let Some(image) = Arc::get_mut(&mut next) {
+            image.bytes.copy_from_slice(&self.working.bytes);
+            image.generation = self.working.generation;
+        }
+        let mut published = PUBLISHED.write().unwrap_or_else(|p| p.into_inner());
+        self.spare = Some(mem::replace(&mut *published, next));
+    }
+}
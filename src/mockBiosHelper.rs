@@ -0,0 +1,229 @@
+#![allow(non_snake_case)]
+// Mock DCS-BIOS for checking what the pages send. It exports a fixed memory
+// image written in control names and keeps every command it receives, with the
+// time it came in. The script is one control per line:
+//   <module> <identifier> <value>
+// e.g. "MetadataStart _ACFT_NAME A-10C_2" or "AV8BNA H2O_MODE 2".
+// Lines starting with # are comments.
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::fs;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::biosServerHelper::serve_export;
+use crate::controlReferenceHelper::{control, controls, Output};
+use crate::dcsBiosHelper::{BiosConnection, BiosPorts, DEFAULT_PORTS};
+use crate::subscriptionHelper::UPDATE_COUNTER_ADDRESS;
+
+// DCS-BIOS exports about 30 frames per second
+const FRAME_INTERVAL: Duration = Duration::from_millis(33);
+
+#[derive(Clone, Debug)]
+pub(crate) struct CapturedCommand {
+    // since the mock started
+    pub(crate) at: Duration,
+    pub(crate) line: String
+}
+
+pub(crate) struct MockBios {
+    start: Instant,
+    echo: bool,
+    commands: Mutex<Vec<CapturedCommand>>,
+    // what it listens on, for set_ports
+    ports: OnceLock<BiosPorts>,
+    stopped: AtomicBool
+}
+
+impl MockBios {
+    fn capture(&self, line: &str) {
+        let command = CapturedCommand { at: self.start.elapsed(), line: line.to_string() };
+        if self.echo {
+            println!("MOCK: +{:.3} {}", command.at.as_secs_f64(), command.line);
+        }
+        self.commands.lock().unwrap_or_else(|p| p.into_inner()).push(command);
+    }
+
+    // Every command so far, oldest first
+    pub(crate) fn commands(&self) -> Vec<CapturedCommand> {
+        self.commands.lock().unwrap_or_else(|p| p.into_inner()).clone()
+    }
+
+    pub(crate) fn ports(&self) -> BiosPorts {
+        self.ports.get().copied().unwrap_or(DEFAULT_PORTS)
+    }
+
+    // Ends the export, TCP clients see the connection closed
+    #[cfg(test)]
+    pub(crate) fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+}
+
+// Export memory of the script, only the words it sets
+pub(crate) struct MemoryScript {
+    pub(crate) words: Vec<(u16, [u8; 2])>
+}
+
+pub(crate) fn load_script(path: &str) -> Result<MemoryScript> {
+    let text = fs::read_to_string(path).with_context(|| format!("Failed to open mock script {}", path))?;
+    let mut memory = vec![0u8; 0x10000];
+    let mut touched = vec![false; 0x8000];
+    let mut modules: Vec<&str> = Vec::new();
+
+    for (i, line) in text.lines().enumerate() {
+        let line_no = i + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut parts = line.splitn(3, ' ');
+        let (module, identifier, value) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(m), Some(i), Some(v)) => (m, i, v),
+            _ => bail!("Line {}: expected '<module> <identifier> <value>', got '{}'", line_no, line),
+        };
+        // DCS exports every display line, the ones the script leaves out are blank
+        if !modules.contains(&module) {
+            modules.push(module);
+            for output in controls(module).flat_map(|c| c.outputs.iter()) {
+                write_string(&mut memory, &mut touched, output, "");
+            }
+        }
+
+        let output = control(module, identifier)
+            .and_then(|c| c.outputs.first())
+            .ok_or_else(|| anyhow!("Line {}: {} has no output {} in the control reference", line_no, module, identifier))?;
+        let address = output.address as usize;
+        if address + (output.max_length as usize).max(2) > memory.len() {
+            bail!("Line {}: {} lies outside the export memory", line_no, identifier);
+        }
+
+        if output.max_length > 0 {
+            if value.len() > output.max_length as usize {
+                bail!("Line {}: '{}' is longer than the {} characters of {}", line_no, value, output.max_length, identifier);
+            }
+            write_string(&mut memory, &mut touched, output, value);
+        } else {
+            let value: u16 = value.trim().parse().with_context(|| format!("Line {}: '{}' is not a number", line_no, value))?;
+            if value > output.mask >> output.shift_by {
+                bail!("Line {}: {} doesn't fit into {}", line_no, value, identifier);
+            }
+            let word = u16::from_le_bytes([memory[address], memory[address + 1]]);
+            let word = (word & !output.mask) | (value << output.shift_by);
+            memory[address..address + 2].copy_from_slice(&word.to_le_bytes());
+            touched[address / 2] = true;
+        }
+    }
+
+    let words = (0..0x8000)
+        .filter(|&w| touched[w])
+        .map(|w| ((w * 2) as u16, [memory[w * 2], memory[w * 2 + 1]]))
+        .collect();
+    Ok(MemoryScript { words })
+}
+
+// Strings are padded with spaces like the cockpit displays, integer outputs are left alone
+fn write_string(memory: &mut [u8], touched: &mut [bool], output: &Output, value: &str) {
+    let start = output.address as usize;
+    let end = start + output.max_length as usize;
+    if output.max_length == 0 || end > memory.len() {
+        return;
+    }
+    for (i, byte) in memory[start..end].iter_mut().enumerate() {
+        *byte = value.as_bytes().get(i).copied().unwrap_or(b' ');
+    }
+    touched[start / 2..end.div_ceil(2)].fill(true);
+}
+
+// One frame like DCS-BIOS sends it: sync, a block per word, update counter last
+fn export_frame(script: &MemoryScript, counter: u8) -> Vec<u8> {
+    let mut frame = vec![0x55; 4];
+    for (address, word) in script.words.iter().chain([(UPDATE_COUNTER_ADDRESS, [counter, 0])].iter()) {
+        frame.extend_from_slice(&address.to_le_bytes());
+        frame.extend_from_slice(&2u16.to_le_bytes());
+        frame.extend_from_slice(word);
+    }
+    frame
+}
+
+// Starts the mock as the local DCS-BIOS on the given ports, echo prints every
+// command as it comes in. The ports it got are in ports().
+pub(crate) fn start_mock_bios(path: &str, connection: BiosConnection, ports: BiosPorts, echo: bool) -> Result<Arc<MockBios>> {
+    let script = Arc::new(load_script(path).with_context(|| format!("Invalid mock script {}", path))?);
+    let mock = Arc::new(MockBios {
+        start: Instant::now(),
+        echo,
+        commands: Mutex::new(Vec::new()),
+        ports: OnceLock::new(),
+        stopped: AtomicBool::new(false),
+    });
+
+    let exporting = mock.clone();
+    let capture = mock.clone();
+    let bound = serve_export(
+        "MOCK",
+        connection,
+        ports,
+        Arc::new(move |send| {
+            let mut counter: u8 = 0;
+            while !exporting.stopped.load(Ordering::SeqCst) {
+                send(&export_frame(&script, counter))?;
+                counter = counter.wrapping_add(1);
+                thread::sleep(FRAME_INTERVAL);
+            }
+            Ok(())
+        }),
+        Arc::new(move |line| capture.capture(line)),
+    )?;
+    let _ = mock.ports.set(bound);
+    Ok(mock)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controlReferenceHelper::load_control_references;
+    use crate::dcsBiosHelper::{DCS_HOST, DEFAULT_PORTS};
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    #[test]
+    fn script_sets_words_by_control_name() {
+        load_control_references("controls").unwrap();
+        let script = load_script("mock/av8b.txt").unwrap();
+        let frame = export_frame(&script, 7);
+        assert_eq!(frame[..4], [0x55; 4]);
+        // the update counter comes last
+        assert_eq!(frame[frame.len() - 6..], [0xfe, 0xff, 0x02, 0x00, 7, 0x00]);
+    }
+
+    #[test]
+    fn mock_on_a_free_port_exports_and_captures() {
+        load_control_references("controls").unwrap();
+        let mock = start_mock_bios("mock/a10c.txt", BiosConnection::Tcp, BiosPorts { tcp: 0, ..DEFAULT_PORTS }, false).unwrap();
+        assert_ne!(mock.ports().tcp, 0);
+
+        let mut client = TcpStream::connect((DCS_HOST, mock.ports().tcp)).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let mut sync = [0u8; 4];
+        client.read_exact(&mut sync).unwrap();
+        assert_eq!(sync, [0x55; 4]);
+
+        client.write_all(b"CDU_A TOGGLE\nCDU_SCROLL 0\n").unwrap();
+        for _ in 0..100 {
+            if mock.commands().len() == 2 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let lines: Vec<String> = mock.commands().into_iter().map(|c| c.line).collect();
+        assert_eq!(lines, ["CDU_A TOGGLE", "CDU_SCROLL 0"]);
+
+        // the client sees the export end
+        mock.stop();
+        let mut rest = Vec::new();
+        client.read_to_end(&mut rest).unwrap();
+    }
+}
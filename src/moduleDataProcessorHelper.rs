@@ -1,460 +1,287 @@
 #![allow(non_snake_case)]
-use std::{collections::HashMap, thread::sleep, time::Duration};
-use phf::phf_map;
-
-use crate::{dcsBiosHelper::{send_button_press, send_button_state_press, send_message_to_dcsbios}, inputHelper::is_button_pressed, types::TextBlock};
-
-
-fn get_value_by_address(values: &HashMap<u16, [u8; 2]>, address: u16) -> [u8; 2] {
-    values.get(&address).copied().unwrap_or([0, 0])
+use std::collections::HashMap;
+use std::sync::mpsc::Receiver;
+use std::sync::LazyLock;
+
+use crate::{dcsBiosHelper::{send_button_press, send_button_presses, send_button_pulse, send_button_state}, gestureHelper::{Gesture, GestureKind}, screenHelper::Screen, types::{Bg, Fg, TextStyle}};
+use crate::controlReferenceHelper::{action_command, state_command};
+use crate::inputHelper::{KeyEvent, KeyEventKind};
+use crate::keyMapHelper::{aircraft_bindings, Binding};
+use crate::memoryImageHelper::MemoryImage;
+use crate::outputHelper::{DrumOutput, IntegerOutput, ScaledOutput, StringOutput};
+use crate::subscriptionHelper::{subscribe_control, BiosEvent};
+
+// Control reference modules, the names of the DCS-BIOS JSON files
+const METADATA: &str = "MetadataStart";
+const A10C: &str = "A-10C";
+const AV8B: &str = "AV8BNA";
+const CH47F: &str = "CH-47F";
+const AH64D: &str = "AH-64D";
+
+// The outputs of every page are looked up in the control reference on the
+// first render, main loads it before any page is drawn
+
+static ACFT_NAME: LazyLock<StringOutput> = LazyLock::new(|| StringOutput::new(METADATA, "_ACFT_NAME"));
+
+// the pilot's CDU is PLT_CDU_LINE1..14
+static CH47F_LINES: LazyLock<Vec<StringOutput>> =
+    LazyLock::new(|| (1..=14).map(|i| StringOutput::new(CH47F, &format!("CPLT_CDU_LINE{}", i))).collect());
+
+static A10C_LINES: LazyLock<Vec<StringOutput>> =
+    LazyLock::new(|| (0..10).map(|i| StringOutput::new(A10C, &format!("CDU_LINE{}", i))).collect());
+
+struct AV8BOutputs {
+    h2o: DrumOutput,
+    rpm: DrumOutput,
+    fuel: DrumOutput,
+    nozzle: ScaledOutput,
+    h2o_mode: IntegerOutput,
+    flap_mode: IntegerOutput,
+    master_arm: IntegerOutput,
+    gear_lever: IntegerOutput,
+    speed_brake: ScaledOutput,
+    mc_ag: IntegerOutput,
+    mc_nav: IntegerOutput,
+    mc_vstol: IntegerOutput
 }
 
-fn addrs_to_65k_num_as_string(values: &HashMap<u16, [u8; 2]>, adrs: Vec<u16>) -> String{
-    let mut text = String::from("");
-
-
-    for add in adrs{
-        let raw = get_value_by_address(values, add);
-        let formatted = (f32::from(u16::from_le_bytes(raw)) / 6553.5).round();
-        text += &formatted.to_string();
-    }
-
-    return text;
+static AV8B_OUTPUTS: LazyLock<AV8BOutputs> = LazyLock::new(|| AV8BOutputs {
+    h2o: DrumOutput::new(AV8B, &["EDP_H2O_1", "EDP_H2O_2"]),
+    rpm: DrumOutput::new(AV8B, &["EDP_RPM_1", "EDP_RPM_2", "EDP_RPM_3", "EDP_RPM_4"]),
+    fuel: DrumOutput::new(AV8B, &["FUEL_TOTAL_1", "FUEL_TOTAL_2", "FUEL_TOTAL_3", "FUEL_TOTAL_4", "FUEL_TOTAL_5"]),
+    nozzle: ScaledOutput::new(AV8B, "EDP_NOZZLE", 125.0),
+    h2o_mode: IntegerOutput::new(AV8B, "H2O_MODE"),
+    flap_mode: IntegerOutput::new(AV8B, "FLAP_MODE"),
+    master_arm: IntegerOutput::new(AV8B, "MASTER_ARM"),
+    gear_lever: IntegerOutput::new(AV8B, "GEAR_LEVER"),
+    speed_brake: ScaledOutput::new(AV8B, "SPEED_BRAKE_POS", 100.0),
+    mc_ag: IntegerOutput::new(AV8B, "MC_AG"),
+    mc_nav: IntegerOutput::new(AV8B, "MC_NAV"),
+    mc_vstol: IntegerOutput::new(AV8B, "MC_VSTOL"),
+});
+
+struct AH64DOutputs {
+    seat: IntegerOutput,
+    plt_ku: StringOutput,
+    cpg_ku: StringOutput,
+    eufd_lines: Vec<StringOutput>
 }
 
-fn map_byte_to_char(b: u8) -> char {
-    match b {
-        0xB0 => '.',                 // custom mapping
-        0xA1 => 'x',                 // custom mapping
-        0xAB => 'y',                 // custom mapping
-        0xBB => 'z',                 // custom mapping
-        0xA9 => 'u',                 // custom mapping
-        0xAE => '^',                 // custom mapping
-        0xB6 => '_',                 // custom mapping
-        0xB1 => '~',                 // custom mapping
-        0x20..=0x7E => b.to_ascii_uppercase() as char,    // printable ASCII
-        _ => '?',                    // fallback for non-ASCII bytes
-    }
+static AH64D_OUTPUTS: LazyLock<AH64DOutputs> = LazyLock::new(|| AH64DOutputs {
+    seat: IntegerOutput::new(AH64D, "SEAT_POSITION"),
+    plt_ku: StringOutput::new(AH64D, "PLT_KU_DISPLAY"),
+    cpg_ku: StringOutput::new(AH64D, "CPG_KU_DISPLAY"),
+    eufd_lines: (1..=7).map(|i| StringOutput::new(AH64D, &format!("PLT_EUFD_LINE{}", i))).collect(),
+});
+
+// One subscription per display the Apache page shows, every other word of
+// the export changes all the time and doesn't need a redraw
+pub fn subscribe_AH64D_page() -> Vec<Receiver<BiosEvent>> {
+    let mut identifiers = vec!["PLT_KU_DISPLAY".to_string(), "CPG_KU_DISPLAY".to_string()];
+    identifiers.extend((1..=7).map(|i| format!("PLT_EUFD_LINE{}", i)));
+    identifiers.iter().map(|identifier| subscribe_control(AH64D, identifier)).collect()
 }
 
-pub fn get_string_by_addr_and_len(
-    values: &HashMap<u16, [u8; 2]>,
-    base_addr: u16,
-    length: u16,                    // length in BYTES; each cell is 2 bytes
-) -> String {
-    let cells = (length / 2) as usize;
-    let mut out = String::with_capacity(length as usize);
-
-    for i in 0..cells {
-        let addr = base_addr.wrapping_add((2 * i) as u16);
-        let [b0, b1] = get_value_by_address(values, addr);
-        out.push(map_byte_to_char(b0));
-        out.push(map_byte_to_char(b1));
+pub fn get_CH47F_text(values: &MemoryImage) -> Screen{
+    let mut screen = Screen::new();
+    let style = TextStyle::new(Fg::Green, Bg::Black);
+    for (row, line) in CH47F_LINES.iter().enumerate(){
+        // title on top, scratchpad at the bottom, in between every line key
+        // has a label line in the small font above its data line
+        let line_style = if(row % 2 == 1 && row < 13) {style.small()} else {style};
+        screen.put_left(row, &line.get(values), &line_style);
     }
-
-    out
+    screen
 }
 
-pub fn get_CH47F_text(values: &HashMap<u16, [u8; 2]>) -> Vec<TextBlock>{
-    let mut cdu_text = String::new();
-    let cplt_cdu_text_addrs: [u16; 14] = [0x9e4e, 0x9e66, 0x9e7e, 0x9e96, 0x9eae, 0x9ec6, 0x9ede, 0x9ef6, 0x9f0e, 0x9f26, 0x9f3e, 0x9f56, 0x9f6e, 0x9f86];
-    let plt_cdu_text_addrs: [u16; 14] = [0x9cdc, 0x9cf4, 0x9d0c, 0x9d24, 0x9d3c, 0x9d54, 0x9d6c, 0x9d84, 0x9d9c, 0x9db4, 0x9dcc, 0x9de4, 0x9dfc, 0x9e14];
-    // let cplt_cdu_text_addrs: [u16; 2] = [0x9e4e, 0x9e66];
-
-    let mut text_raw = "".to_string();
-    for addr in cplt_cdu_text_addrs{
-        text_raw = get_string_by_addr_and_len(values, addr, 24);
-        cdu_text.push_str(&text_raw);
+pub fn get_AV8B_text(values: &MemoryImage) -> Screen{
+    let mut screen = Screen::new();
+    let value_style = TextStyle::new(Fg::Green, Bg::Black);
+    let switch_style = TextStyle::new(Fg::Red, Bg::Black);
+    let outputs = &*AV8B_OUTPUTS;
+
+    // the last H2O digit is painted on
+    let h2O_text = "H2O : ".to_string() + &outputs.h2o.get(values) + "0";
+    let rpm_text = "RPM: ".to_string() + &outputs.rpm.get(values);
+    let fuel_text = "FUEL: ".to_string() + &outputs.fuel.get(values);
+    let nozzle = outputs.nozzle.get(values);
+    let nozzle_text = format!("NOZ: {}", nozzle.round() as i32);
+
+    screen.put_left(0, &h2O_text, &value_style);
+    screen.put_right(0, &rpm_text, &value_style);
+    screen.put_left(1, &fuel_text, &value_style);
+    screen.put(1, 15, &nozzle_text, &value_style);
+
+    let h2o_switch_lookup = ["LDG","OFF","TO"];
+    let h2o_switch_pos = outputs.h2o_mode.get(values);
+    if let Some(pos) = h2o_switch_lookup.get(h2o_switch_pos as usize){
+        screen.put_left(2, &("H2O POS: ".to_string() + pos), &switch_style);
     }
-    cdu_text.push_str(&" ".repeat(24*14-text_raw.len()));
-
-
-    let mut testVec: Vec<TextBlock> = Vec::new();
-    testVec.push(
-        TextBlock {
-            text: cdu_text,
-            bg: (String::from("black")),
-            fg: (String::from("green"))
-        }
-    );
-    // println!("{:?}", testVec);
-    return testVec;
-}
-
-pub fn get_AV8B_text(values: &HashMap<u16, [u8; 2]>) -> Vec<TextBlock>{
-    let mut testVec: Vec<TextBlock> = Vec::new();
-
-
-    let h2O_addrs = vec![0x78aa, 0x78ac];
-    let mut h2O_text = "H2O : ".to_string();
-    h2O_text += &addrs_to_65k_num_as_string(values, h2O_addrs);
-    h2O_text += &"0".to_string();
-
-
-    let rpm_addrs = vec![0x7890, 0x7892, 0x7894, 0x7896];
-    let mut rpm_text = "RPM: ".to_string();
-    rpm_text += &addrs_to_65k_num_as_string(values, rpm_addrs)[0..=3];
-
-    let fuel_addrs = vec![0x78b2, 0x78b4, 0x78b6, 0x78b8, 0x78ba];
-    let mut fuel_text = "FUEL: ".to_string();
-    fuel_text += &addrs_to_65k_num_as_string(values, fuel_addrs);
-
-    let nozzle_addrs = vec![0x78ae];
-    let mut nozzle_text = "NOZ: ".to_string();
-    nozzle_text += &format!("{}", (addrs_to_65k_num_as_string(values, nozzle_addrs).parse::<f32>().unwrap_or(0.0) * 12.5) as i32);
-
-    // The space after TO is important
-    let h2o_switch_lookup = ["LDG","OFF","TO "];
-    let h2o_switch_pos_LE = get_value_by_address(values, 0x783c);
-    let mut h2o_switch_pos_BE = u16::from_le_bytes(h2o_switch_pos_LE);
-    h2o_switch_pos_BE = (h2o_switch_pos_BE & 0x000c)>>2;
-    let h2o_switch_text =
-        if (h2o_switch_pos_BE < 3)
-            {"H2O POS: ".to_string() +
-            h2o_switch_lookup[h2o_switch_pos_BE as usize]}
-        else {"".to_string()};
 
     let flaps_switch_lookup = ["STOL","AUTO","CRSE"];
-    let flaps_switch_pos_LE = get_value_by_address(values, 0x783a);
-    let mut flaps_switch_pos_BE = u16::from_le_bytes(flaps_switch_pos_LE);
-    flaps_switch_pos_BE = (flaps_switch_pos_BE & 0x0180)>>7;
-    let flaps_switch_text =
-        if (flaps_switch_pos_BE < 3)
-            {"FLAPS POS: ".to_string() +
-            flaps_switch_lookup[flaps_switch_pos_BE as usize]}
-        else {"".to_string()};
-
-    let combined_string = h2O_text + &" ".repeat(6) + &rpm_text + &fuel_text + &" ".repeat(4) + &nozzle_text + &" ".repeat(9-nozzle_text.len());
-    // let combined_len = combined_string.len();
-
+    let flaps_switch_pos = outputs.flap_mode.get(values);
+    if let Some(pos) = flaps_switch_lookup.get(flaps_switch_pos as usize){
+        screen.put_left(3, &("FLAPS POS: ".to_string() + pos), &switch_style);
+    }
 
     // Master arm
-    let master_arm = (u16::from_le_bytes(get_value_by_address(values, 0x7836))&0x4000)>>14;
-    let master_arm_text = "MASTER ARM: ".to_string() + &{if(master_arm == 1) {"ON "} else {"OFF"}};
+    let master_arm = outputs.master_arm.is_set(values);
+    let master_arm_text = "MASTER ARM: ".to_string() + if(master_arm) {"ON"} else {"OFF"};
+    screen.put_left(4, &master_arm_text, &switch_style);
 
     // Landing gear
-    let ldg_gear = (u16::from_le_bytes(get_value_by_address(values, 0x783a))&0x8000)>>15;
-    let ldg_gear_text = "GEAR: ".to_string() + &{if(ldg_gear == 1) {"UP"} else {"DN"}};
+    let ldg_gear = outputs.gear_lever.is_set(values);
+    let ldg_gear_text = "GEAR: ".to_string() + if(ldg_gear) {"UP"} else {"DN"};
+    screen.put_right(4, &ldg_gear_text, &switch_style);
 
     // Air brake
-    let air_brk = (((u16::from_le_bytes(get_value_by_address(values, 0x794e)) as f32)/655.35).round() as u8).to_string();
-    let air_brk_text = "AIR BRK: ".to_string() + &air_brk;
-
-    let ldg_gear_text = "GEAR: ".to_string() + &{if(ldg_gear == 1) {"UP"} else {"DN"}};
-
+    let air_brk = outputs.speed_brake.get(values).round() as u8;
+    screen.put_left(5, &format!("AIR BRK: {}", air_brk), &switch_style);
 
     // A/G Master mode
-    let ag_mms = (u16::from_le_bytes(get_value_by_address(values, 0x7880))&0x0800)>>11;
-    let nav_mms = (u16::from_le_bytes(get_value_by_address(values, 0x7880))&0x0200)>>9;
-    let vstol_mms = (u16::from_le_bytes(get_value_by_address(values, 0x7880))&0x0400)>>10;
-
-    // The spaces here are important
     let master_mode_text = {
-        if (ag_mms == 1) {"A/G "}
-        else if (nav_mms   ==1) {"NAV "}
-        else if (vstol_mms ==1) {"VTOL"}
-        else {"    "}
+        if (outputs.mc_ag.is_set(values)) {"A/G"}
+        else if (outputs.mc_nav.is_set(values)) {"NAV"}
+        else if (outputs.mc_vstol.is_set(values)) {"VTOL"}
+        else {""}
     };
+    screen.put_left(6, master_mode_text, &switch_style);
 
-    let switch_text = h2o_switch_text + &" ".repeat(12) + &flaps_switch_text + &" ".repeat(9) + &master_arm_text + &" " + &ldg_gear_text + &air_brk_text + &" ".repeat(24-air_brk_text.len()) + &master_mode_text;
-
-    testVec.push(
-        TextBlock {
-            text: combined_string,
-            // +&" ".repeat(136-combined_len),
-            bg: (String::from("black")),
-            fg: (String::from("green"))
-        }
-    );
-    testVec.push(
-        TextBlock {
-            text: switch_text,
-            // +&" ".repeat(136-combined_len),
-            bg: (String::from("black")),
-            fg: (String::from("red"))
-        }
-    );
-
-    return testVec;
+    screen
 }
 
-pub fn get_A10C2_text(values: &HashMap<u16, [u8; 2]>) -> Vec<TextBlock>{
-    let mut cdu_text = String::new();
-    let cdu_addrs: [u16; 10] = [0x11c0, 0x11d8, 0x11f0, 0x1208, 0x1220, 0x1238, 0x1250, 0x1268, 0x1280, 0x1298];
-
-    cdu_text.push_str(&" ".repeat(24*4));
-    for addr in cdu_addrs{
-        let text_raw = get_string_by_addr_and_len(values, addr, 24);
-        cdu_text.push_str(&text_raw);
+pub fn get_A10C2_text(values: &MemoryImage) -> Screen{
+    // The CDU has 10 lines, they go on the bottom 10 rows of the display
+    let mut screen = Screen::new();
+    let style = TextStyle::new(Fg::Green, Bg::Black);
+    for (i, line) in A10C_LINES.iter().enumerate(){
+        // lines 1, 3, 5 and 7 label the line select keys below them, small font like in the jet
+        let line_style = if(i % 2 == 1 && i < 9) {style.small()} else {style};
+        screen.put_left(4 + i, &line.get(values), &line_style);
     }
-
-    let mut testVec: Vec<TextBlock> = Vec::new();
-    testVec.push(
-        TextBlock {
-            text: cdu_text,
-            bg: (String::from("black")),
-            fg: (String::from("green"))
-        }
-    );
-    return testVec;
+    screen
 }
 
-pub fn handle_A10C2_input(){
-    let a10c2_button_keymappings: phf::Map<&'static str, &'static str> = phf_map! {
-        "INIT REF" => "CDU_SYS TOGGLE",
-        "RTE" => "CDU_NAV TOGGLE",
-        "CLB" => "CDU_WP TOGGLE",
-        "CRZ" => "CDU_OSET TOGGLE",
-        "DES" => "CDU_MK TOGGLE",
-        "PROG" => "CDU_FPM TOGGLE",
-        "L3"=>"CDU_LSK_3L TOGGLE",
-        "L4"=>"CDU_LSK_5L TOGGLE",
-        "L5"=>"CDU_LSK_7L TOGGLE",
-        "L6"=>"CDU_LSK_9L TOGGLE",
-        "R1"=>"CDU_PREV TOGGLE",
-        "R3"=>"CDU_LSK_3R TOGGLE",
-        "R4"=>"CDU_LSK_5R TOGGLE",
-        "R5"=>"CDU_LSK_7R TOGGLE",
-        "R6"=>"CDU_LSK_9R TOGGLE",
-        "."=>"CDU_POINT TOGGLE",
-        "/"=>"CDU_SLASH TOGGLE",
-        "A"=>"CDU_A TOGGLE",
-        "B"=>"CDU_B TOGGLE",
-        "C"=>"CDU_C TOGGLE",
-        "D"=>"CDU_D TOGGLE",
-        "E"=>"CDU_E TOGGLE",
-        "F"=>"CDU_F TOGGLE",
-        "G"=>"CDU_G TOGGLE",
-        "H"=>"CDU_H TOGGLE",
-        "I"=>"CDU_I TOGGLE",
-        "J"=>"CDU_J TOGGLE",
-        "K"=>"CDU_K TOGGLE",
-        "L"=>"CDU_L TOGGLE",
-        "M"=>"CDU_M TOGGLE",
-        "N"=>"CDU_N TOGGLE",
-        "O"=>"CDU_O TOGGLE",
-        "P"=>"CDU_P TOGGLE",
-        "Q"=>"CDU_Q TOGGLE",
-        "R"=>"CDU_R TOGGLE",
-        "S"=>"CDU_S TOGGLE",
-        "T"=>"CDU_T TOGGLE",
-        "U"=>"CDU_U TOGGLE",
-        "V"=>"CDU_V TOGGLE",
-        "W"=>"CDU_W TOGGLE",
-        "X"=>"CDU_X TOGGLE",
-        "Y"=>"CDU_Y TOGGLE",
-        "Z"=>"CDU_Z TOGGLE",
-        "1"=>"CDU_1 TOGGLE",
-        "2"=>"CDU_2 TOGGLE",
-        "3"=>"CDU_3 TOGGLE",
-        "4"=>"CDU_4 TOGGLE",
-        "5"=>"CDU_5 TOGGLE",
-        "6"=>"CDU_6 TOGGLE",
-        "7"=>"CDU_7 TOGGLE",
-        "8"=>"CDU_8 TOGGLE",
-        "9"=>"CDU_9 TOGGLE",
-        "0"=>"CDU_0 TOGGLE",
-        "SP"=>"CDU_SPC TOGGLE",
-        "DEL"=>"CDU_BCK TOGGLE",
-        "CLR"=>"CDU_CLR TOGGLE",
-    };
-
-    let a10c2_rocker_keymappings: phf::Map<&'static str, [&'static  str;2]> = phf_map! {
-        "L1" => ["CDU_SCROLL 0", "CDU_SCROLL 1"],
-        "L2" => ["CDU_SCROLL 2", "CDU_SCROLL 1"],
-        "PREV PAGE" => ["CDU_PG 2", "CDU_PG 1"],
-        "NEXT PAGE" => ["CDU_PG 0", "CDU_PG 1"],
-        "BRT+" => ["CDU_DATA 2", "CDU_DATA 1"],
-        "BRT-" => ["CDU_DATA 0", "CDU_DATA 1"],
-    };
-
-    let buttons = [
-        "L3", "L4", "L5", "L6", "R1", "R3", "R4", "R5", "R6", "INIT REF", "RTE", "CLB", "CRZ", "DES", "PROG", ".", "/", "A", "B", "C", "D", "E", "F", "G", "H", "I", "J", "K", "L", "M", "N", "O", "P", "Q", "R", "S", "T", "U", "V", "W", "X", "Y", "Z", "1", "2", "3", "4", "5", "6", "7", "8", "9", "0", "SP", "DEL", "CLR"
-    ];
-
-    let rockers = [
-        "L1", "L2", "PREV PAGE", "NEXT PAGE", "BRT+", "BRT-"
-    ];
-
-    for button in buttons{
-        if is_button_pressed(button){
-            let message = match a10c2_button_keymappings.get(button) {
-                Some(&i) => i,
-                None => panic!("Wrong A10 index"), // unknown button
-            };
-            send_button_press(message);
-        }
-    }
-
-    for rocker in rockers{
-        if is_button_pressed(rocker){
-            let message = match a10c2_rocker_keymappings.get(rocker) {
-                Some(&i) => i,
-                None => panic!("Wrong A10 index"), // unknown button
-            };
-            send_button_state_press(message[0], message[1]);
+// Momentary keys that are down in DCS, key -> command that lets go of it
+pub type HeldKeys = HashMap<&'static str, String>;
+
+// What the keys do comes from keys/aircraft/<aircraft>.json, aircraft is the
+// name DCS reports, {seat} in a control name becomes seat. Buttons go on the
+// moment a key goes down, holding it sends nothing more unless it repeats
+// (see REPEAT_KEYS). Momentary buttons and rockers follow the key instead, down and up,
+// unless the key repeats, then they are pushed and let go like a button.
+// Called every tick whatever the page or mode, without an aircraft (search
+// mode, unplugged unit) keys that are down in DCS are only let go of.
+pub fn handle_aircraft_input(aircraft: Option<&str>, events: &[KeyEvent], gestures: &[Gesture], seat: &str, held: &mut HeldKeys){
+    let bindings = aircraft.and_then(aircraft_bindings);
+    let control = |name: &str| name.replace("{seat}", seat);
+
+    // the second key of a chord only makes the chord, EXEC of MENU+EXEC isn't entered in the KU
+    let chorded = |key: &str| gestures.iter().any(|g| matches!(g.kind, GestureKind::Chord(second) if second == key));
+
+    // in the order they came in, a key pressed and released within one tick is up again
+    for event in events{
+        match event.kind {
+            KeyEventKind::Pressed => {
+                let Some(bindings) = bindings else { continue };
+                if chorded(event.key) {
+                    continue;
+                }
+                if let Some(Binding::State(binding)) = bindings.press.get(event.key){
+                    let name = control(&binding.control);
+                    send_button_state(&state_command(&bindings.module, &name, binding.held));
+                    held.insert(event.key, state_command(&bindings.module, &name, binding.released));
+                }
+            }
+            KeyEventKind::Released => {
+                if let Some(release) = held.remove(event.key){
+                    send_button_state(&release);
+                }
+            }
         }
     }
-}
 
-pub fn handle_AH64D_input(values: &HashMap<u16, [u8; 2]>){
-    let is_cpg = AH64D_isCpg(values);
-
-    let ah64d_pilot_kdu_keymappings: phf::Map<&'static str, &'static str> = phf_map! {
-        "EXEC" => "_KU_EXEC TOGGLE",
-        "."=>"_KU_DOT TOGGLE",
-        "+-"=>"_KU_SIGN TOGGLE",
-        "/"=>"_KU_SLASH TOGGLE",
-        "A"=>"_KU_A TOGGLE",
-        "B"=>"_KU_B TOGGLE",
-        "C"=>"_KU_C TOGGLE",
-        "D"=>"_KU_D TOGGLE",
-        "E"=>"_KU_E TOGGLE",
-        "F"=>"_KU_F TOGGLE",
-        "G"=>"_KU_G TOGGLE",
-        "H"=>"_KU_H TOGGLE",
-        "I"=>"_KU_I TOGGLE",
-        "J"=>"_KU_J TOGGLE",
-        "K"=>"_KU_K TOGGLE",
-        "L"=>"_KU_L TOGGLE",
-        "M"=>"_KU_M TOGGLE",
-        "N"=>"_KU_N TOGGLE",
-        "O"=>"_KU_O TOGGLE",
-        "P"=>"_KU_P TOGGLE",
-        "Q"=>"_KU_Q TOGGLE",
-        "R"=>"_KU_R TOGGLE",
-        "S"=>"_KU_S TOGGLE",
-        "T"=>"_KU_T TOGGLE",
-        "U"=>"_KU_U TOGGLE",
-        "V"=>"_KU_V TOGGLE",
-        "W"=>"_KU_W TOGGLE",
-        "X"=>"_KU_X TOGGLE",
-        "Y"=>"_KU_Y TOGGLE",
-        "Z"=>"_KU_Z TOGGLE",
-        "1"=>"_KU_1 TOGGLE",
-        "2"=>"_KU_2 TOGGLE",
-        "3"=>"_KU_3 TOGGLE",
-        "4"=>"_KU_4 TOGGLE",
-        "5"=>"_KU_5 TOGGLE",
-        "6"=>"_KU_6 TOGGLE",
-        "7"=>"_KU_7 TOGGLE",
-        "8"=>"_KU_8 TOGGLE",
-        "9"=>"_KU_9 TOGGLE",
-        "0"=>"_KU_0 TOGGLE",
-        "SP"=>"_KU_SPC TOGGLE",
-        "DEL"=>"_KU_BKS TOGGLE",
-        "CLR"=>"_KU_CLR TOGGLE",
-        "NEXT PAGE"=>"_KU_RIGHT TOGGLE",
-        "PREV PAGE"=>"_KU_LEFT TOGGLE",
+    let Some(bindings) = bindings else {
+        return;
     };
-
-    let buttons = [".", "/", "+-", "A", "B", "C", "D", "E", "F", "G", "H", "I", "J", "K", "L", "M", "N", "O", "P", "Q", "R", "S", "T", "U", "V", "W", "X", "Y", "Z", "1", "2", "3", "4", "5", "6", "7", "8", "9", "0", "SP", "DEL", "CLR", "PREV PAGE", "NEXT PAGE", "EXEC"
-    ];
-
-    let prefix = if (is_cpg) {"CPG"} else {"PLT"};
-
-    for button in buttons{
-        if is_button_pressed(button){
-            let message = match ah64d_pilot_kdu_keymappings.get(button) {
-                Some(&i) => i,
-                None => panic!("Wrong AH64D index"), // unknown button
-            };
-            send_button_press(&format!("{prefix}{message}"));
+    let module = bindings.module.as_str();
+    for gesture in gestures{
+        match gesture.kind {
+            GestureKind::LongPress => {
+                // e.g. CLR twice, the first one only clears an error message
+                if let Some(binding) = bindings.long_press.get(gesture.key){
+                    send_button_presses(&action_command(module, &control(&binding.control)), binding.presses);
+                }
+            }
+            // keys without a binding are ignored
+            GestureKind::Press => {
+                match bindings.press.get(gesture.key) {
+                    Some(Binding::Button(name)) => send_button_press(&action_command(module, &control(name))),
+                    Some(Binding::Pulse(binding)) => {
+                        let name = control(&binding.control);
+                        send_button_pulse(&state_command(module, &name, binding.held), &state_command(module, &name, binding.released));
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
         }
     }
 }
 
-fn AH64D_isCpg(values:&HashMap<u16, [u8;2]>)->bool{
-    return (u16::from_le_bytes(get_value_by_address(values, 0x8750))&0x0100) == 256;
+pub fn AH64D_isCpg(values: &MemoryImage)->bool{
+    AH64D_OUTPUTS.seat.is_set(values)
 }
 
-fn CH47F_isCpg(values:&HashMap<u16, [u8;2]>)->bool{
-    // NOT CORRECT
-    return (u16::from_le_bytes(get_value_by_address(values, 0x8750))&0x0100) == 256;
-}
+// is_cpg decides which KU is shown as the own one, see AH64D_isCpg for the seat in DCS
+pub fn get_AH64D_text(values: &MemoryImage, is_cpg: bool)-> Screen{
+    let outputs = &*AH64D_OUTPUTS;
+    let (own_kdu, other_kdu) = if is_cpg {(&outputs.cpg_ku, &outputs.plt_ku)} else {(&outputs.plt_ku, &outputs.cpg_ku)};
+    let mut screen = Screen::new();
 
-pub fn get_AH64D_text(values: &HashMap<u16, [u8;2]>)-> Vec<TextBlock>{
-    // checks
-    let is_cpg = AH64D_isCpg(values);
-    let own_kdu_adr: u16 = if is_cpg {0x80ac} else {0x808e};
-    let other_kdu_adr: u16 = if !is_cpg {0x80ac} else {0x808e};
-    let mut adv_string = String::new();
-    let mut warn_str = String::new();
-    let mut caut_str = String::new();
-    let mut own_kdu_str = String::new();
-    let mut lines: Vec<String> = Vec::new();
-    let line_base_id = 0x80c2;
-    let mut blocks_vec: Vec<TextBlock> = Vec::new();
-
-    for i in 0..=6{
-        let id = line_base_id + i*56;
-        lines.push(get_string_by_addr_and_len(values, id, 56));
-    }
+    // padded, the columns below are cut out of the full 56 characters
+    let lines: Vec<String> = outputs.eufd_lines.iter().map(|line| format!("{:<56}", line.get(values))).collect();
 
     // Advisories
-    for i in 0..6{
-        let adv = lines[i][38..].to_string();
-        adv_string += &adv;
-        adv_string += &" ".repeat(24-adv.len());
+    let adv_style = TextStyle::new(Fg::Yellow, Bg::Black);
+    for (row, line) in lines.iter().take(6).enumerate(){
+        screen.put_left(row, &line[38..], &adv_style);
     }
-    blocks_vec.push(
-        TextBlock {
-            text: adv_string,
-            bg: (String::from("black")),
-            fg: (String::from("yellow"))
-        }
-    );
 
     // Warnings
-    for i in 0..3{
-        let warn = lines[i][19..37].to_string();
-        warn_str += &warn;
-        warn_str += &" ".repeat(24-warn.len());
+    let warn_style = TextStyle::new(Fg::Red, Bg::Black);
+    for (i, line) in lines.iter().take(3).enumerate(){
+        screen.put_left(6 + i, &line[19..37], &warn_style);
     }
-    blocks_vec.push(
-        TextBlock {
-            text: warn_str,
-            bg: (String::from("black")),
-            fg: (String::from("red"))
-        }
-    );
 
     // Cautions
-    for i in 0..3{
-        let caut = lines[i][0..18].to_string();
-        caut_str += &caut;
-        caut_str += &" ".repeat(24-caut.len());
+    let caut_style = TextStyle::new(Fg::Orange, Bg::Black);
+    for (i, line) in lines.iter().take(3).enumerate(){
+        screen.put_left(9 + i, &line[0..18], &caut_style);
     }
-    blocks_vec.push(
-        TextBlock {
-            text: caut_str,
-            bg: (String::from("black")),
-            fg: (String::from("orange"))
-        }
-    );
 
     // Keyboard Display Unit
-    own_kdu_str += &get_string_by_addr_and_len(values, own_kdu_adr, 22);
-    own_kdu_str += "  ";
-    blocks_vec.push(
-        TextBlock {
-            text: own_kdu_str,
-            bg: (String::from("black")),
-            fg: (String::from("green"))
-        }
-    );
-
-    let other_kdu_string = get_string_by_addr_and_len(values, other_kdu_adr, 22);
-    blocks_vec.push(
-        TextBlock {
-            text: other_kdu_string,
-            bg: (String::from("black")),
-            fg: (String::from("white"))
-        }
-    );
-    return blocks_vec;
+    screen.put_left(12, &own_kdu.get(values), &TextStyle::new(Fg::Green, Bg::Black));
+    // Other crew member's KU is only for reference, it gets the small font
+    screen.put_left(13, &other_kdu.get(values), &TextStyle::new(Fg::White, Bg::Black).small());
+    screen
+}
+
+// Shown while there is no DCS-BIOS data, or for aircraft without a page
+pub fn get_status_text(connected: bool, module_name: &str)-> Screen{
+    let mut screen = Screen::new();
+    if(!connected){
+        screen.put_centre(6, "WAITING FOR DCS", &TextStyle::new(Fg::Yellow, Bg::Black));
+        return screen;
+    }
+    screen.put_centre(6, "CONNECTED", &TextStyle::new(Fg::Green, Bg::Black));
+    let module = module_name.trim_end_matches(['\0', ' ']);
+    let module = if module.is_empty() { "NO AIRCRAFT" } else { module };
+    screen.put_centre(7, module, &TextStyle::new(Fg::White, Bg::Black).small());
+    screen
 }
 
-pub fn get_module_name(values: &HashMap<u16, [u8; 2]>) -> String{
-    return get_string_by_addr_and_len(values, 0x0000, 24);
+// Without the padding, the name the key bindings are filed under
+pub fn get_module_name(values: &MemoryImage) -> String{
+    ACFT_NAME.get(values).trim_end().to_string()
 }
\ No newline at end of file
@@ -0,0 +1,191 @@
+#![allow(non_snake_case)]
+// Typed reads of DCS-BIOS outputs from a snapshot of the export memory.
+// Built once from the control reference (the pages keep them in statics), read every frame.
+
+use crate::controlReferenceHelper::{output, Output};
+use crate::memoryImageHelper::MemoryImage;
+
+// Switch positions, lamps and other values packed into part of a word
+pub(crate) struct IntegerOutput(&'static Output);
+
+impl IntegerOutput {
+    pub(crate) fn new(module: &str, identifier: &str) -> Self {
+        IntegerOutput(output(module, identifier))
+    }
+
+    pub(crate) fn get(&self, values: &MemoryImage) -> u16 {
+        (values.word(self.0.address) & self.0.mask) >> self.0.shift_by
+    }
+
+    pub(crate) fn is_set(&self, values: &MemoryImage) -> bool {
+        self.get(values) != 0
+    }
+}
+
+// Gauges, DCS-BIOS exports them as 0..65535 over the whole range
+pub(crate) struct ScaledOutput {
+    output: IntegerOutput,
+    full_scale: f32
+}
+
+impl ScaledOutput {
+    // full_scale is what 65535 stands for, e.g. 100.0 for percent
+    pub(crate) fn new(module: &str, identifier: &str, full_scale: f32) -> Self {
+        ScaledOutput { output: IntegerOutput::new(module, identifier), full_scale }
+    }
+
+    pub(crate) fn get(&self, values: &MemoryImage) -> f32 {
+        f32::from(self.output.get(values)) / 65535.0 * self.full_scale
+    }
+}
+
+// Counter made of drums, most significant first. Every drum is exported as a
+// gauge where 65535 is one full turn, so each tenth is one digit.
+pub(crate) struct DrumOutput(Vec<IntegerOutput>);
+
+impl DrumOutput {
+    pub(crate) fn new(module: &str, identifiers: &[&str]) -> Self {
+        DrumOutput(identifiers.iter().map(|i| IntegerOutput::new(module, i)).collect())
+    }
+
+    // One character per drum, a drum between 9 and 0 reads 0
+    pub(crate) fn get(&self, values: &MemoryImage) -> String {
+        self.0
+            .iter()
+            .map(|drum| {
+                let digit = (f32::from(drum.get(values)) / 6553.5).round() as u32 % 10;
+                char::from_digit(digit, 10).unwrap_or('0')
+            })
+            .collect()
+    }
+}
+
+fn map_byte_to_char(b: u8) -> char {
+    match b {
+        0xB0 => '.',                 // custom mapping
+        0xA1 => 'x',                 // custom mapping
+        0xAB => 'y',                 // custom mapping
+        0xBB => 'z',                 // custom mapping
+        0xA9 => 'u',                 // custom mapping
+        0xAE => '^',                 // custom mapping
+        0xB6 => '_',                 // custom mapping
+        0xB1 => '~',                 // custom mapping
+        0x20..=0x7E => b.to_ascii_uppercase() as char,    // printable ASCII
+        _ => '?',                    // fallback for non-ASCII bytes
+    }
+}
+
+// Display lines. The special symbols of the cockpit fonts become the lower case
+// letters the custom PFP font draws them with, the rest is upper case.
+pub(crate) struct StringOutput(&'static Output);
+
+impl StringOutput {
+    pub(crate) fn new(module: &str, identifier: &str) -> Self {
+        StringOutput(output(module, identifier))
+    }
+
+    // max_length characters
+    pub(crate) fn get(&self, values: &MemoryImage) -> String {
+        values.bytes(self.0.address, self.0.max_length).iter().map(|&b| map_byte_to_char(b)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controlReferenceHelper::load_control_references;
+
+    const AV8B: &str = "AV8BNA";
+
+    fn load() {
+        load_control_references("controls").unwrap();
+    }
+
+    // Gauge words for drums showing the given digits, v / 6553.5 rounds to the digit
+    fn drum_words(first: u16, digits: &[u16]) -> Vec<(u16, [u8; 2])> {
+        digits.iter().enumerate().map(|(i, d)| (first + 2 * i as u16, ((*d as f32 * 6553.5) as u16).to_le_bytes())).collect()
+    }
+
+    #[test]
+    fn integer_output_masks_and_shifts() {
+        load();
+        // H2O_MODE is bits 2..3 of 0x783c, the bits around it belong to other switches
+        let mode = IntegerOutput::new(AV8B, "H2O_MODE");
+        let values = MemoryImage::from_words(&[(0x783c, 0xfff3u16.to_le_bytes())]);
+        assert_eq!(mode.get(&values), 0);
+        assert!(!mode.is_set(&values));
+        let values = MemoryImage::from_words(&[(0x783c, 0x0008u16.to_le_bytes())]);
+        assert_eq!(mode.get(&values), 2);
+        assert!(mode.is_set(&values));
+
+        let gear = IntegerOutput::new(AV8B, "GEAR_LEVER");
+        assert!(gear.is_set(&MemoryImage::from_words(&[(0x783a, 0x8000u16.to_le_bytes())])));
+        assert!(!gear.is_set(&MemoryImage::from_words(&[(0x783a, 0x7fffu16.to_le_bytes())])));
+    }
+
+    #[test]
+    fn missing_control_reads_zero() {
+        load();
+        let values = MemoryImage::from_words(&[(0x0000, [0xff, 0xff])]);
+        assert_eq!(IntegerOutput::new(AV8B, "NOT_A_CONTROL").get(&values), 0);
+        assert_eq!(StringOutput::new(AV8B, "NOT_A_CONTROL").get(&values), "");
+    }
+
+    #[test]
+    fn scaled_output_spans_full_scale() {
+        load();
+        let brake = ScaledOutput::new(AV8B, "SPEED_BRAKE_POS", 100.0);
+        let at = |v: u16| brake.get(&MemoryImage::from_words(&[(0x794e, v.to_le_bytes())]));
+        assert_eq!(at(0), 0.0);
+        assert_eq!(at(65535), 100.0);
+        assert!((at(32768) - 50.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn drum_output_reads_one_digit_per_drum() {
+        load();
+        let rpm = DrumOutput::new(AV8B, &["EDP_RPM_1", "EDP_RPM_2", "EDP_RPM_3", "EDP_RPM_4"]);
+        assert_eq!(rpm.get(&MemoryImage::from_words(&drum_words(0x7890, &[1, 0, 5, 2]))), "1052");
+        assert_eq!(rpm.get(&MemoryImage::new()), "0000");
+    }
+
+    #[test]
+    fn drum_output_wraps_past_nine() {
+        load();
+        let drum = DrumOutput::new(AV8B, &["EDP_RPM_1"]);
+        let at = |v: u16| drum.get(&MemoryImage::from_words(&[(0x7890, v.to_le_bytes())]));
+        assert_eq!(at(58982), "9");
+        // a full turn and a drum past half way from 9 to 0 both read 0
+        assert_eq!(at(65535), "0");
+        assert_eq!(at(62260), "0");
+        // rounded to the nearest digit
+        assert_eq!(at(3276), "0");
+        assert_eq!(at(3277), "1");
+    }
+
+    #[test]
+    fn cockpit_glyphs_map_to_the_custom_font() {
+        let glyphs = [(0xB0, '.'), (0xA1, 'x'), (0xAB, 'y'), (0xBB, 'z'), (0xA9, 'u'), (0xAE, '^'), (0xB6, '_'), (0xB1, '~')];
+        for (byte, glyph) in glyphs {
+            assert_eq!(map_byte_to_char(byte), glyph, "{:#04x}", byte);
+        }
+        // lower case is reserved for the symbols above
+        assert_eq!(map_byte_to_char(b'a'), 'A');
+        assert_eq!(map_byte_to_char(b'7'), '7');
+        assert_eq!(map_byte_to_char(b' '), ' ');
+        assert_eq!(map_byte_to_char(0x00), '?');
+        assert_eq!(map_byte_to_char(0xFF), '?');
+    }
+
+    #[test]
+    fn string_output_reads_max_length_characters() {
+        load();
+        let mut words = Vec::new();
+        for (i, pair) in b"wpt\xb0ab".chunks(2).enumerate() {
+            words.push((0x11c0 + 2 * i as u16, [pair[0], pair[1]]));
+        }
+        let line = StringOutput::new("A-10C", "CDU_LINE0").get(&MemoryImage::from_words(&words));
+        assert_eq!(line.len(), 24);
+        assert!(line.starts_with("WPT.AB"), "{:?}", line);
+    }
+}
@@ -0,0 +1,83 @@
+#![allow(non_snake_case)]
+
+use crate::types::TextStyle;
+
+pub(crate) const SCREEN_COLS: usize = 24;
+pub(crate) const SCREEN_ROWS: usize = 14;
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Cell {
+    pub(crate) ch: char,
+    pub(crate) style: TextStyle
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Cell { ch: ' ', style: TextStyle::default() }
+    }
+}
+
+// Cell addressed display buffer, row 0 is the top line.
+// Everything written outside of the grid is clipped instead of wrapping
+// into the next row, so a long string can never shift the rest of the page.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Screen {
+    cells: Vec<Cell>
+}
+
+impl Default for Screen {
+    fn default() -> Self {
+        Screen::new()
+    }
+}
+
+impl Screen {
+    pub(crate) fn new() -> Self {
+        Screen { cells: vec![Cell::default(); SCREEN_COLS * SCREEN_ROWS] }
+    }
+
+    // Writes text starting at (row, col). Characters past the end of the row are dropped.
+    pub(crate) fn put(&mut self, row: usize, col: usize, text: &str, style: &TextStyle) {
+        if row >= SCREEN_ROWS {
+            return;
+        }
+        for (i, ch) in text.chars().enumerate() {
+            let c = col + i;
+            if c >= SCREEN_COLS {
+                break;
+            }
+            self.cells[row * SCREEN_COLS + c] = Cell { ch, style: *style };
+        }
+    }
+
+    pub(crate) fn set(&mut self, row: usize, col: usize, cell: Cell) {
+        if row < SCREEN_ROWS && col < SCREEN_COLS {
+            self.cells[row * SCREEN_COLS + col] = cell;
+        }
+    }
+
+    pub(crate) fn put_left(&mut self, row: usize, text: &str, style: &TextStyle) {
+        self.put(row, 0, text, style);
+    }
+
+    pub(crate) fn put_right(&mut self, row: usize, text: &str, style: &TextStyle) {
+        let len = text.chars().count();
+        if len > SCREEN_COLS {
+            // keep the end of the string, that's the part that is right aligned
+            let tail: String = text.chars().skip(len - SCREEN_COLS).collect();
+            self.put(row, 0, &tail, style);
+            return;
+        }
+        self.put(row, SCREEN_COLS - len, text, style);
+    }
+
+    pub(crate) fn put_centre(&mut self, row: usize, text: &str, style: &TextStyle) {
+        let len = text.chars().count();
+        self.put(row, SCREEN_COLS.saturating_sub(len) / 2, text, style);
+    }
+
+    // Cells in the order the display expects them (row by row, left to right)
+    pub(crate) fn cells(&self) -> &[Cell] {
+        &self.cells
+    }
+}
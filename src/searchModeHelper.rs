@@ -5,13 +5,7 @@ use std::{env, io, path::{Path, PathBuf}, sync::{Mutex, OnceLock}};
 
 use phf::phf_map;
 
-use crate::{inputHelper::{get_button_pressed_char, is_button_pressed}, types::{rwr_info_lut, RwrInfo, TextBlock}};
-
-static SEARCH_STRING: OnceLock<Mutex<String>> = OnceLock::new();
-
-fn get_search_string() -> &'static Mutex<String> {
-    SEARCH_STRING.get_or_init(|| Mutex::new(String::new()))
-}
+use crate::{gestureHelper::{Gesture, GestureKind}, inputHelper::key_char, screenHelper::Screen, types::{rwr_info_lut, Bg, Fg, RwrInfo, TextStyle}};
 
 fn search_string_to_lookup_code(search_string: &str) -> String{
     match search_string{
@@ -40,56 +34,49 @@ fn search_string_to_lookup_code(search_string: &str) -> String{
     }
 }
 
-pub fn get_search_mode_disp()-> Vec<TextBlock>{
+// search_string belongs to the CDU that shows the page, so each unit searches on its own
+// Every key press types once, only DEL repeats while held
+pub fn get_search_mode_disp(gestures: &[Gesture], search_string: &mut String)-> Screen{
     // Constructing searchstring
-    let mut search_string = get_search_string().lock().unwrap();
-
-    let mut res: Vec<TextBlock> = Vec::new();
-
-    if(is_button_pressed("CLR")){
-        search_string.clear();
-    }
-    if(is_button_pressed("DEL")){
-        search_string.pop();
-    }
-    let pressed_button = get_button_pressed_char();
-    if(pressed_button != ""){
-        search_string.push_str(&pressed_button);
+    let mut screen = Screen::new();
+    let style = TextStyle::new(Fg::Green, Bg::Black);
+
+    for gesture in gestures{
+        match (gesture.kind, gesture.key) {
+            (GestureKind::Press | GestureKind::LongPress, "CLR") => search_string.clear(),
+            (GestureKind::Press, "DEL") => { search_string.pop(); }
+            (GestureKind::Press, key) => {
+                if let Some(c) = key_char(key){
+                    search_string.push(c);
+                }
+            }
+            _ => {}
+        }
     }
 
-    let rwr_code = search_string_to_lookup_code(&search_string);
+    let rwr_code = search_string_to_lookup_code(search_string);
     let rwr_info = rwr_info_lut(&rwr_code);
 
-    // Just formatting shenanigans
-    let abrv_string = String::from("ABRV: ") + &rwr_info.abrv + &" ".repeat(24-6-rwr_info.abrv.len()).as_str();
-    let nato_string = String::from("NATO: ") + &rwr_info.nato_name + &" ".repeat(24-6-rwr_info.nato_name.len()).as_str();
-    let rwr_string = String::from("RWR: ") + &rwr_info.rwr_code + &" ".repeat(24-5-rwr_info.rwr_code.len()).as_str();
-    let rng_nm_string = String::from("RNG NM: ") + &rwr_info.rng_nm + &" ".repeat(24-8-rwr_info.rng_nm.len()).as_str();
-    let alt_ft_string = String::from("ALT FT: ") + &rwr_info.alt_ft + &" ".repeat(24-8-rwr_info.alt_ft.len()).as_str();
-    let flr_string = String::from("FLARE: ") + &rwr_info.flr + &" ".repeat(24-7-rwr_info.flr.len()).as_str();
-    let chf_string = String::from("CHAFF: ") + &rwr_info.chf + &" ".repeat(24-7-rwr_info.chf.len()).as_str();
-    let ecm_string = String::from("ECM: ") + &rwr_info.ecm + &" ".repeat(24-5-rwr_info.ecm.len()).as_str();
-    let lock_time_string = String::from("LOCK TIME: ") + &rwr_info.lock_time + &" ".repeat(24-11-rwr_info.lock_time.len()).as_str();
-    let guidance_string = String::from("GUIDANCE: ") + &rwr_info.guidance + &" ".repeat(24-10-rwr_info.guidance.len()).as_str();
-
-    let final_string = abrv_string + &nato_string + &rwr_string + &rng_nm_string + &alt_ft_string + &flr_string + &chf_string + &ecm_string + &lock_time_string +&guidance_string;
-
-    res.push(
-        TextBlock {
-            text: String::from("SEARCH STRING: ") + search_string.as_str() + " ".repeat(9-search_string.len()).as_str(),
-            bg: (String::from("black")),
-            fg: (String::from("green"))
-        }
-    );
-
-    res.push(
-        TextBlock {
-            text: final_string,
-            bg: (String::from("black")),
-            fg: (String::from("green"))
-        }
-    );
-
+    screen.put_left(0, &(String::from("SEARCH STRING: ") + search_string.as_str()), &style);
+
+    let fields = [
+        ("ABRV: ", &rwr_info.abrv),
+        ("NATO: ", &rwr_info.nato_name),
+        ("RWR: ", &rwr_info.rwr_code),
+        ("RNG NM: ", &rwr_info.rng_nm),
+        ("ALT FT: ", &rwr_info.alt_ft),
+        ("FLARE: ", &rwr_info.flr),
+        ("CHAFF: ", &rwr_info.chf),
+        ("ECM: ", &rwr_info.ecm),
+        ("LOCK TIME: ", &rwr_info.lock_time),
+        ("GUIDANCE: ", &rwr_info.guidance),
+    ];
+    // CDU style: labels in the small font, values in the large one
+    let label_style = style.small();
+    for (row, (label, value)) in fields.iter().enumerate() {
+        screen.put_left(row + 1, label, &label_style);
+        screen.put(row + 1, label.len(), value, &style);
+    }
 
-    return res;
-}
\ No newline at end of file
+    screen
+}
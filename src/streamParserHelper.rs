@@ -0,0 +1,185 @@
+#![allow(non_snake_case)]
+// DCS-BIOS export stream parser. The stream is a sequence of frames:
+//   55 55 55 55                      sync
+//   addr(u16 le) len(u16 le) data    repeated, len bytes of data per block
+// TCP reads can end anywhere, so the parser keeps its state between calls and
+// every word is reported as soon as both of its bytes arrived. Four 0x55 in a
+// row always mean sync, wherever they show up, which gets the parser back on
+// track after garbage or a dropped packet.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    WaitForSync,
+    AddressLow,
+    AddressHigh,
+    CountLow,
+    CountHigh,
+    DataLow,
+    DataHigh
+}
+
+pub(crate) struct ExportParser {
+    state: State,
+    sync_count: u8,
+    address: u16,
+    count: u16,
+    data: u8
+}
+
+impl Default for ExportParser {
+    fn default() -> Self {
+        ExportParser::new()
+    }
+}
+
+impl ExportParser {
+    pub(crate) fn new() -> Self {
+        ExportParser { state: State::WaitForSync, sync_count: 0, address: 0, count: 0, data: 0 }
+    }
+
+    // Calls on_write(address, word bytes) for every complete word in data
+    pub(crate) fn feed(&mut self, data: &[u8], on_write: &mut impl FnMut(u16, [u8; 2])) {
+        for &byte in data {
+            self.step(byte, on_write);
+        }
+    }
+
+    fn step(&mut self, byte: u8, on_write: &mut impl FnMut(u16, [u8; 2])) {
+        match self.state {
+            State::WaitForSync => {}
+            State::AddressLow => {
+                self.address = byte as u16;
+                self.state = State::AddressHigh;
+            }
+            State::AddressHigh => {
+                self.address |= (byte as u16) << 8;
+                // a sync in the middle of the stream is just another frame start
+                self.state = if self.address == 0x5555 { State::WaitForSync } else { State::CountLow };
+            }
+            State::CountLow => {
+                self.count = byte as u16;
+                self.state = State::CountHigh;
+            }
+            State::CountHigh => {
+                self.count |= (byte as u16) << 8;
+                self.state = if self.count == 0 { State::AddressLow } else { State::DataLow };
+            }
+            State::DataLow => {
+                self.data = byte;
+                self.count = self.count.saturating_sub(1);
+                // an odd length leaves half a word, it is dropped so the next
+                // block starts where the sender put it
+                self.state = if self.count == 0 { State::AddressLow } else { State::DataHigh };
+            }
+            State::DataHigh => {
+                on_write(self.address, [self.data, byte]);
+                self.address = self.address.wrapping_add(2);
+                self.count = self.count.saturating_sub(1);
+                self.state = if self.count == 0 { State::AddressLow } else { State::DataLow };
+            }
+        }
+
+        if byte == 0x55 {
+            self.sync_count += 1;
+        } else {
+            self.sync_count = 0;
+        }
+        if self.sync_count == 4 {
+            self.state = State::AddressLow;
+            self.sync_count = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A frame the way DCS-BIOS sends it: changed words in blocks, a string
+    // across several words, the update counter at 0xfffe last
+    fn frame(counter: u8) -> Vec<u8> {
+        let mut frame = vec![0x55, 0x55, 0x55, 0x55];
+        for (address, data) in [
+            (0x0000u16, &b"A-10C_2\0"[..]),
+            (0x11c0, &b"WAYPOINT    "[..]),
+            (0x8750, &[0x00, 0x01][..]),
+            (0xfffe, &[counter, 0x00][..]),
+        ] {
+            frame.extend_from_slice(&address.to_le_bytes());
+            frame.extend_from_slice(&(data.len() as u16).to_le_bytes());
+            frame.extend_from_slice(data);
+        }
+        frame
+    }
+
+    fn parse(parser: &mut ExportParser, parts: &[&[u8]]) -> Vec<(u16, [u8; 2])> {
+        let mut writes = Vec::new();
+        for part in parts {
+            parser.feed(part, &mut |address, word| writes.push((address, word)));
+        }
+        writes
+    }
+
+    #[test]
+    fn frame_is_parsed_into_words() {
+        let writes = parse(&mut ExportParser::new(), &[&frame(7)]);
+        assert_eq!(writes.len(), 4 + 6 + 1 + 1);
+        assert_eq!(writes[0], (0x0000, *b"A-"));
+        assert_eq!(writes[3], (0x0006, [b'2', 0]));
+        assert_eq!(writes[4], (0x11c0, *b"WA"));
+        assert_eq!(writes[9], (0x11ca, *b"  "));
+        assert_eq!(writes[10], (0x8750, [0x00, 0x01]));
+        assert_eq!(writes[11], (0xfffe, [7, 0]));
+    }
+
+    #[test]
+    fn split_at_every_offset() {
+        let stream = [frame(1), frame(2)].concat();
+        let whole = parse(&mut ExportParser::new(), &[&stream]);
+        for split in 0..=stream.len() {
+            let (first, second) = stream.split_at(split);
+            assert_eq!(parse(&mut ExportParser::new(), &[first, second]), whole, "split at {}", split);
+        }
+        let bytes: Vec<&[u8]> = stream.chunks(1).collect();
+        assert_eq!(parse(&mut ExportParser::new(), &bytes), whole);
+    }
+
+    #[test]
+    fn nothing_before_the_first_sync() {
+        let stream = [&[0x34, 0x12, 0x02, 0x00, 0xaa, 0xbb][..], &frame(3)].concat();
+        assert_eq!(parse(&mut ExportParser::new(), &[&stream]), parse(&mut ExportParser::new(), &[&frame(3)]));
+    }
+
+    // A packet got lost in the middle of a block, the next frame is found by its sync
+    #[test]
+    fn sync_in_the_middle_of_a_block() {
+        let mut stream = vec![0x55, 0x55, 0x55, 0x55, 0xc0, 0x11, 0x18, 0x00, b'W', b'A'];
+        stream.extend(frame(4));
+        let writes = parse(&mut ExportParser::new(), &[&stream]);
+        // the sync bytes are taken as data until the fourth one
+        assert_eq!(writes[..3], [(0x11c0, *b"WA"), (0x11c2, [0x55, 0x55]), (0x11c4, [0x55, 0x55])]);
+        assert_eq!(writes[3..], parse(&mut ExportParser::new(), &[&frame(4)]));
+    }
+
+    #[test]
+    fn odd_length_block_drops_the_half_word() {
+        let mut stream = vec![0x55, 0x55, 0x55, 0x55, 0x00, 0x10, 0x03, 0x00, 0x01, 0x02, 0x03];
+        stream.extend_from_slice(&[0xfe, 0xff, 0x02, 0x00, 0x09, 0x00]);
+        let writes = parse(&mut ExportParser::new(), &[&stream]);
+        assert_eq!(writes, [(0x1000, [0x01, 0x02]), (0xfffe, [0x09, 0x00])]);
+    }
+
+    // DCS-BIOS sends neighbouring words in one block, the counter can be the last of them
+    #[test]
+    fn update_counter_at_the_end_of_a_block() {
+        let stream = [0x55, 0x55, 0x55, 0x55, 0xfc, 0xff, 0x04, 0x00, 0x01, 0x00, 0x2a, 0x00];
+        let writes = parse(&mut ExportParser::new(), &[&stream]);
+        assert_eq!(writes, [(0xfffc, [0x01, 0x00]), (0xfffe, [0x2a, 0x00])]);
+    }
+
+    #[test]
+    fn zero_length_block_is_skipped() {
+        let stream = [0x55, 0x55, 0x55, 0x55, 0x00, 0x10, 0x00, 0x00, 0xfe, 0xff, 0x02, 0x00, 0x05, 0x00];
+        assert_eq!(parse(&mut ExportParser::new(), &[&stream]), [(0xfffe, [0x05, 0x00])]);
+    }
+}
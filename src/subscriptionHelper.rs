@@ -0,0 +1,131 @@
+#![allow(non_snake_case)]
+// Change notifications from the export stream. A subscriber gets a channel and
+// is told which words of its range changed, and when DCS-BIOS finished a frame.
+
+use std::ops::RangeInclusive;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Mutex, MutexGuard};
+
+use crate::controlReferenceHelper::output;
+
+// DCS-BIOS writes its update counter last in every frame
+pub(crate) const UPDATE_COUNTER_ADDRESS: u16 = 0xfffe;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum BiosEvent {
+    // a word in the subscribed range got a new value
+    Changed { address: u16, value: [u8; 2] },
+    // everything of the frame is in the map now
+    FrameEnd,
+    // no more data, the map was cleared
+    Disconnected
+}
+
+struct Subscriber {
+    // None only wants frame ends and disconnects
+    range: Option<RangeInclusive<u16>>,
+    sender: Sender<BiosEvent>
+}
+
+static SUBSCRIBERS: Mutex<Vec<Subscriber>> = Mutex::new(Vec::new());
+
+fn subscribe(range: Option<RangeInclusive<u16>>) -> Receiver<BiosEvent> {
+    let (sender, receiver) = mpsc::channel();
+    SUBSCRIBERS.lock().unwrap_or_else(|p| p.into_inner()).push(Subscriber { range, sender });
+    receiver
+}
+
+// Dropping the receiver ends the subscription
+pub(crate) fn subscribe_frames() -> Receiver<BiosEvent> {
+    subscribe(None)
+}
+
+pub(crate) fn subscribe_range(range: RangeInclusive<u16>) -> Receiver<BiosEvent> {
+    subscribe(Some(range))
+}
+
+// All words of a control, a whole display line for strings
+pub(crate) fn subscribe_control(module: &str, identifier: &str) -> Receiver<BiosEvent> {
+    let output = output(module, identifier);
+    let last = output.address.saturating_add(output.max_length.max(2) - 1);
+    subscribe_range(output.address..=last)
+}
+
+// Held by the stream reader while it applies one read, so the subscriber
+// list is locked once and not for every word
+pub(crate) struct Notifier<'a>(MutexGuard<'a, Vec<Subscriber>>);
+
+pub(crate) fn notifier() -> Notifier<'static> {
+    Notifier(SUBSCRIBERS.lock().unwrap_or_else(|p| p.into_inner()))
+}
+
+impl Notifier<'_> {
+    fn send(&mut self, event: BiosEvent, wants: impl Fn(&Subscriber) -> bool) {
+        // subscribers that dropped their receiver are removed
+        self.0.retain(|s| !wants(s) || s.sender.send(event).is_ok());
+    }
+
+    pub(crate) fn changed(&mut self, address: u16, value: [u8; 2]) {
+        self.send(BiosEvent::Changed { address, value }, |s| {
+            s.range.as_ref().is_some_and(|r| r.contains(&address))
+        });
+    }
+
+    pub(crate) fn frame_end(&mut self) {
+        self.send(BiosEvent::FrameEnd, |_| true);
+    }
+
+    pub(crate) fn disconnected(&mut self) {
+        self.send(BiosEvent::Disconnected, |_| true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Readers of other tests notify the same subscribers, only the
+    // changes are looked at and the addresses are not used by any control
+    fn changes_in(events: &[BiosEvent]) -> Vec<u16> {
+        events.iter().filter_map(|e| match e {
+            BiosEvent::Changed { address, .. } => Some(*address),
+            _ => None,
+        }).collect()
+    }
+
+    #[test]
+    fn range_subscriber_gets_only_its_words() {
+        let receiver = subscribe_range(0xff10..=0xff13);
+        let mut notifier = notifier();
+        for address in [0xff0e, 0xff10, 0xff12, 0xff14] {
+            notifier.changed(address, [1, 2]);
+        }
+        notifier.frame_end();
+        drop(notifier);
+        let events: Vec<BiosEvent> = receiver.try_iter().collect();
+        assert!(events.contains(&BiosEvent::FrameEnd));
+        assert_eq!(changes_in(&events), [0xff10, 0xff12]);
+    }
+
+    #[test]
+    fn frame_subscriber_gets_no_changes() {
+        let receiver = subscribe_frames();
+        let mut notifier = notifier();
+        notifier.changed(0xff20, [1, 2]);
+        notifier.disconnected();
+        drop(notifier);
+        let events: Vec<BiosEvent> = receiver.try_iter().collect();
+        assert!(events.contains(&BiosEvent::Disconnected));
+        assert!(changes_in(&events).is_empty());
+    }
+
+    #[test]
+    fn dropped_receiver_is_unsubscribed() {
+        let receiver = subscribe_range(0xff30..=0xff31);
+        drop(receiver);
+        let mut notifier = notifier();
+        let before = notifier.0.len();
+        notifier.changed(0xff30, [1, 2]);
+        assert_eq!(notifier.0.len(), before - 1);
+    }
+}
@@ -0,0 +1,325 @@
+#![allow(non_snake_case)]
+// Everything that talks HID goes through Transport, so the bridge can run
+// against the real device, an in-memory mock or a recorder wrapped around either.
+
+use anyhow::{anyhow, Context, Result};
+use hidapi::HidDevice;
+#[cfg(test)]
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub trait Transport: Send {
+    // Sends one report, the first byte is the report id
+    fn write(&self, data: &[u8]) -> Result<usize>;
+    // Returns 0 if nothing arrived while in non-blocking mode
+    fn read(&self, buf: &mut [u8]) -> Result<usize>;
+    fn set_blocking_mode(&self, blocking: bool) -> Result<()>;
+    // Goes up every time the device was opened again after a disconnect,
+    // transports that can't reconnect stay at 0
+    fn generation(&self) -> u64 {
+        0
+    }
+}
+
+impl Transport for HidDevice {
+    fn write(&self, data: &[u8]) -> Result<usize> {
+        Ok(HidDevice::write(self, data)?)
+    }
+
+    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        Ok(HidDevice::read(self, buf)?)
+    }
+
+    fn set_blocking_mode(&self, blocking: bool) -> Result<()> {
+        Ok(HidDevice::set_blocking_mode(self, blocking)?)
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn write(&self, data: &[u8]) -> Result<usize> {
+        (**self).write(data)
+    }
+
+    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        (**self).read(buf)
+    }
+
+    fn set_blocking_mode(&self, blocking: bool) -> Result<()> {
+        (**self).set_blocking_mode(blocking)
+    }
+
+    fn generation(&self) -> u64 {
+        (**self).generation()
+    }
+}
+
+impl<T: Transport + Sync + ?Sized> Transport for Arc<T> {
+    fn write(&self, data: &[u8]) -> Result<usize> {
+        (**self).write(data)
+    }
+
+    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        (**self).read(buf)
+    }
+
+    fn set_blocking_mode(&self, blocking: bool) -> Result<()> {
+        (**self).set_blocking_mode(blocking)
+    }
+
+    fn generation(&self) -> u64 {
+        (**self).generation()
+    }
+}
+
+// Keeps every written report and hands out queued input reports, for the
+// tests that drive the writer, the reader and a whole Cdu without hardware
+#[cfg(test)]
+#[derive(Default)]
+pub struct MockTransport {
+    written: Mutex<Vec<Vec<u8>>>,
+    reads: Mutex<VecDeque<Vec<u8>>>,
+    blocking: Mutex<bool>,
+    // reads and writes fail like they do for a unit that was pulled out
+    unplugged: std::sync::atomic::AtomicBool
+}
+
+#[cfg(test)]
+impl MockTransport {
+    pub fn new() -> Self {
+        MockTransport::default()
+    }
+
+    // Queues a report that the next read returns
+    pub fn push_read(&self, report: &[u8]) {
+        self.reads.lock().unwrap().push_back(report.to_vec());
+    }
+
+    pub fn written(&self) -> Vec<Vec<u8>> {
+        self.written.lock().unwrap().clone()
+    }
+
+    pub fn clear_written(&self) {
+        self.written.lock().unwrap().clear();
+    }
+
+    pub fn is_blocking(&self) -> bool {
+        *self.blocking.lock().unwrap()
+    }
+
+    pub fn set_unplugged(&self, unplugged: bool) {
+        self.unplugged.store(unplugged, Ordering::SeqCst);
+    }
+
+    fn check_plugged(&self) -> Result<()> {
+        if self.unplugged.load(Ordering::SeqCst) {
+            return Err(anyhow!("mock device unplugged"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl Transport for MockTransport {
+    fn write(&self, data: &[u8]) -> Result<usize> {
+        self.check_plugged()?;
+        self.written.lock().unwrap().push(data.to_vec());
+        Ok(data.len())
+    }
+
+    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        self.check_plugged()?;
+        match self.reads.lock().unwrap().pop_front() {
+            Some(report) => {
+                let n = report.len().min(buf.len());
+                buf[..n].copy_from_slice(&report[..n]);
+                Ok(n)
+            }
+            None => Ok(0),
+        }
+    }
+
+    fn set_blocking_mode(&self, blocking: bool) -> Result<()> {
+        *self.blocking.lock().unwrap() = blocking;
+        Ok(())
+    }
+}
+
+// Log file shared by all transports wrapped with it.
+// Written reports are plain hex lines, so a recording can be used as INIT_PATH
+// directly. The time and read reports go into comments, which the loader skips:
+//   f2420041... # +1.234 out
+//   # +1.240 in 01000000...
+#[derive(Clone)]
+pub struct Recorder {
+    file: Arc<Mutex<BufWriter<File>>>,
+    start: Instant
+}
+
+impl Recorder {
+    pub fn create(path: &str) -> Result<Self> {
+        let file = File::create(path).with_context(|| format!("Failed to create HID recording {}", path))?;
+        Ok(Recorder { file: Arc::new(Mutex::new(BufWriter::new(file))), start: Instant::now() })
+    }
+
+    pub fn wrap<T: Transport>(&self, inner: T) -> RecordingTransport<T> {
+        RecordingTransport { inner, recorder: self.clone() }
+    }
+
+    fn log(&self, line: String) {
+        let mut file = match self.file.lock() {
+            Ok(f) => f,
+            Err(p) => p.into_inner(),
+        };
+        // a failing recorder must not take the bridge down with it
+        if let Err(e) = writeln!(file, "{}", line).and_then(|_| file.flush()) {
+            eprintln!("PFP_WRITER: HID recording failed: {}", e);
+        }
+    }
+}
+
+pub struct RecordingTransport<T: Transport> {
+    inner: T,
+    recorder: Recorder
+}
+
+impl<T: Transport> Transport for RecordingTransport<T> {
+    fn write(&self, data: &[u8]) -> Result<usize> {
+        let res = self.inner.write(data);
+        let secs = self.recorder.start.elapsed().as_secs_f64();
+        self.recorder.log(format!("{} # +{:.3} out", hex::encode(data), secs));
+        res
+    }
+
+    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n > 0 {
+            let secs = self.recorder.start.elapsed().as_secs_f64();
+            self.recorder.log(format!("# +{:.3} in {}", secs, hex::encode(&buf[..n])));
+        }
+        Ok(n)
+    }
+
+    fn set_blocking_mode(&self, blocking: bool) -> Result<()> {
+        self.inner.set_blocking_mode(blocking)
+    }
+}
+
+pub type Opener = Arc<dyn Fn() -> Result<Box<dyn Transport>> + Send + Sync>;
+
+const REOPEN_INTERVAL: Duration = Duration::from_millis(1000);
+
+// Drops the device on the first failed read or write and keeps opening it
+// again (at most once per REOPEN_INTERVAL) until it is back, so a bumped
+// USB cable doesn't need a restart.
+pub struct ReconnectingTransport {
+    name: String,
+    open: Opener,
+    device: Mutex<Option<Box<dyn Transport>>>,
+    blocking: Mutex<Option<bool>>,
+    last_attempt: Mutex<Instant>,
+    generation: AtomicU64
+}
+
+impl ReconnectingTransport {
+    // Fails if the device can't be opened right away
+    pub fn new(name: &str, open: Opener) -> Result<Self> {
+        let device = open()?;
+        Ok(ReconnectingTransport {
+            name: name.to_string(),
+            open,
+            device: Mutex::new(Some(device)),
+            blocking: Mutex::new(None),
+            last_attempt: Mutex::new(Instant::now()),
+            generation: AtomicU64::new(0),
+        })
+    }
+
+    fn with_device<R>(&self, f: impl FnOnce(&dyn Transport) -> Result<R>) -> Result<R> {
+        let mut device = match self.device.lock() {
+            Ok(d) => d,
+            Err(p) => p.into_inner(),
+        };
+        if device.is_none() {
+            *device = self.reopen();
+        }
+        let dev = device.as_ref().ok_or_else(|| anyhow!("{} is disconnected", self.name))?;
+        let res = f(dev.as_ref());
+        if let Err(e) = &res {
+            eprintln!("PFP_WRITER: Lost {} ({}), trying to reconnect", self.name, e);
+            *device = None;
+        }
+        res
+    }
+
+    fn reopen(&self) -> Option<Box<dyn Transport>> {
+        let mut last_attempt = match self.last_attempt.lock() {
+            Ok(t) => t,
+            Err(p) => p.into_inner(),
+        };
+        if last_attempt.elapsed() < REOPEN_INTERVAL {
+            return None;
+        }
+        *last_attempt = Instant::now();
+
+        let device = (self.open)().ok()?;
+        let blocking = *self.blocking.lock().unwrap();
+        if let Some(blocking) = blocking {
+            device.set_blocking_mode(blocking).ok()?;
+        }
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        println!("PFP_WRITER: Reconnected {}", self.name);
+        Some(device)
+    }
+}
+
+impl Transport for ReconnectingTransport {
+    fn write(&self, data: &[u8]) -> Result<usize> {
+        self.with_device(|d| d.write(data))
+    }
+
+    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        self.with_device(|d| d.read(buf))
+    }
+
+    fn set_blocking_mode(&self, blocking: bool) -> Result<()> {
+        *self.blocking.lock().unwrap() = Some(blocking);
+        self.with_device(|d| d.set_blocking_mode(blocking))
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reconnecting_transport_reopens_after_a_failed_write() {
+        let mock = Arc::new(MockTransport::new());
+        let device = mock.clone();
+        let open: Opener = Arc::new(move || Ok(Box::new(device.clone()) as Box<dyn Transport>));
+        let transport = ReconnectingTransport::new("mock", open).unwrap();
+        transport.set_blocking_mode(false).unwrap();
+        transport.write(&[0xF2]).unwrap();
+        assert_eq!(transport.generation(), 0);
+
+        mock.set_unplugged(true);
+        assert!(transport.write(&[0xF2]).is_err());
+        mock.set_unplugged(false);
+        mock.set_blocking_mode(true).unwrap();
+        // no second attempt before REOPEN_INTERVAL
+        assert!(transport.write(&[0xF2]).is_err());
+        std::thread::sleep(REOPEN_INTERVAL);
+        transport.write(&[0xF2]).unwrap();
+        assert_eq!(transport.generation(), 1);
+        // the reopened device gets the blocking mode set before
+        assert!(!mock.is_blocking());
+        assert_eq!(mock.written().len(), 2);
+    }
+}
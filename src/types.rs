@@ -1,11 +1,93 @@
-use phf::phf_map;
 
-#[derive(Clone, Debug)]
+// Foreground colours the display knows, the value is what goes into the colour word
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Fg {
+    Orange = 0,
+    White = 1,
+    Cyan = 2,
+    Green = 3,
+    Magenta = 4,
+    Red = 5,
+    Yellow = 6
+}
 
-pub(crate) struct TextBlock {
-    pub(crate) text: String,
-    pub(crate) bg: String,
-    pub(crate) fg: String
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Bg {
+    Black = 0,
+    Green = 1,
+    Gray = 2,
+    Orange = 3,
+    Purple = 4
+}
+
+impl Fg {
+    pub(crate) const ALL: [Fg; 7] = [Fg::Orange, Fg::White, Fg::Cyan, Fg::Green, Fg::Magenta, Fg::Red, Fg::Yellow];
+}
+
+impl Bg {
+    pub(crate) const ALL: [Bg; 5] = [Bg::Black, Bg::Green, Bg::Gray, Bg::Orange, Bg::Purple];
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum FontSize {
+    Large,
+    Small
+}
+
+// Attributes of a single character cell
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct TextStyle {
+    pub(crate) fg: Fg,
+    pub(crate) bg: Bg,
+    pub(crate) size: FontSize
+}
+
+impl TextStyle {
+    pub(crate) const fn new(fg: Fg, bg: Bg) -> Self {
+        TextStyle { fg, bg, size: FontSize::Large }
+    }
+
+    pub(crate) const fn small(self) -> Self {
+        TextStyle { size: FontSize::Small, ..self }
+    }
+
+    // Every character on the display is sent as [word lo, word hi, char] where
+    // word = 0x21 + 0x21 * fg + 0xC * bg, plus 0x16B for the small font.
+    // The word is a little endian u16: e.g. yellow on purple is 0x117, so the
+    // high byte is not always 0x00.
+    // The small font offset comes from the colour test page the WinWing software
+    // sends after the font upload (end of PFP3N.txt): the same characters are sent
+    // once with 0x42/0x63/0x84.. and once with 0x1AD/0x1CE/0x1EF.., the second
+    // set is the second (smaller) font of the init sequence.
+    pub(crate) const fn encode(&self) -> [u8; 2] {
+        let mut word: u16 = 0x21 + 0x21 * (self.fg as u16) + 0xC * (self.bg as u16);
+        if let FontSize::Small = self.size {
+            word += 0x16B;
+        }
+        word.to_le_bytes()
+    }
+
+    // Inverse of encode, None for words no fg/bg/size combination produces
+    pub(crate) fn decode(bytes: [u8; 2]) -> Option<Self> {
+        for fg in Fg::ALL {
+            for bg in Bg::ALL {
+                let style = TextStyle::new(fg, bg);
+                if style.encode() == bytes {
+                    return Some(style);
+                }
+                if style.small().encode() == bytes {
+                    return Some(style.small());
+                }
+            }
+        }
+        None
+    }
+}
+
+impl Default for TextStyle {
+    fn default() -> Self {
+        TextStyle::new(Fg::White, Bg::Black)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -14,8 +96,11 @@ pub(crate) struct RwrInfo{
     pub(crate) nato_name: String,
     pub(crate) rwr_code: String,
     pub(crate) rng_nm: String,
-    pub(crate) rng_km: String,
     pub(crate) alt_ft: String,
+    // metric values of the reference, the search page shows the imperial ones
+    #[allow(dead_code)]
+    pub(crate) rng_km: String,
+    #[allow(dead_code)]
     pub(crate) alt_mtr: String,
     pub(crate) flr: String,
     pub(crate) chf: String,
@@ -50,76 +135,44 @@ pub fn rwr_info_lut(rwr_code: &str) -> RwrInfo {
     }
 }
 
-pub(crate) static PFP_KEYCODES: phf::Map<&'static str, u8> = phf_map! {
-    "L1" => 0,
-    "L2" => 1,
-    "L3" => 2,
-    "L4" => 3,
-    "L5" => 4,
-    "L6" => 5,
-    "R1" => 6,
-    "R2" => 7,
-    "R3" => 8,
-    "R4" => 9,
-    "R5" => 10,
-    "R6" => 11,
-    "INIT REF" => 12,
-    "RTE" => 13,
-    "CLB" => 14,
-    "CRZ" => 15,
-    "DES" => 16,
-    "BRT-" => 17,
-    "BRT+" => 18,
-    "MENU" => 19,
-    "LEGS" => 20,
-    "DEP ARR" => 21,
-    "HOLD" => 22,
-    "PROG" => 23,
-    "EXEC" => 24,
-    "N1 LIMIT" => 25,
-    "FIX" => 26,
-    "PREV PAGE" => 27,
-    "NEXT PAGE" => 28,
-    "1" => 29,
-    "2" => 30,
-    "3" => 31,
-    "4" => 32,
-    "5" => 33,
-    "6" => 34,
-    "7" => 35,
-    "8" => 36,
-    "9" => 37,
-    "." => 38,
-    "0" => 39,
-    "+-" => 40,
-    "A" => 41,
-    "B" => 42,
-    "C" => 43,
-    "D" => 44,
-    "E" => 45,
-    "F" => 46,
-    "G" => 47,
-    "H" => 48,
-    "I" => 49,
-    "J" => 50,
-    "K" => 51,
-    "L" => 52,
-    "M" => 53,
-    "N" => 54,
-    "O" => 55,
-    "P" => 56,
-    "Q" => 57,
-    "R" => 58,
-    "S" => 59,
-    "T" => 60,
-    "U" => 61,
-    "V" => 62,
-    "W" => 63,
-    "X" => 64,
-    "Y" => 65,
-    "Z" => 66,
-    "SP" => 67,
-    "DEL" => 68,
-    "/" => 69,
-    "CLR" => 70,
-};
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_matches_colour_word_formula() {
+        for fg in Fg::ALL {
+            for bg in Bg::ALL {
+                let large = 0x21 + 0x21 * fg as u16 + 0xC * bg as u16;
+                assert_eq!(TextStyle::new(fg, bg).encode(), large.to_le_bytes(), "{:?} on {:?}", fg, bg);
+                assert_eq!(TextStyle::new(fg, bg).small().encode(), (large + 0x16B).to_le_bytes(), "small {:?} on {:?}", fg, bg);
+            }
+        }
+    }
+
+    #[test]
+    fn encode_known_words() {
+        // white on black is what the original pages sent for everything
+        assert_eq!(TextStyle::new(Fg::White, Bg::Black).encode(), [0x42, 0x00]);
+        assert_eq!(TextStyle::new(Fg::Yellow, Bg::Purple).encode(), [0x17, 0x01]);
+        assert_eq!(TextStyle::new(Fg::White, Bg::Black).small().encode(), [0xAD, 0x01]);
+    }
+
+    #[test]
+    fn decode_round_trips_every_style() {
+        for fg in Fg::ALL {
+            for bg in Bg::ALL {
+                for style in [TextStyle::new(fg, bg), TextStyle::new(fg, bg).small()] {
+                    assert_eq!(TextStyle::decode(style.encode()), Some(style));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn decode_rejects_unknown_words() {
+        assert_eq!(TextStyle::decode([0x00, 0x00]), None);
+        assert_eq!(TextStyle::decode([0x43, 0x00]), None);
+        assert_eq!(TextStyle::decode([0xFF, 0xFF]), None);
+    }
+}
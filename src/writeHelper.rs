@@ -1,109 +1,26 @@
 #![allow(non_snake_case)]
 use anyhow::{ Context, Result };
-use hidapi::{ HidDevice };
-use std::env;
-use std::fs::File;
-use std::io::{self, BufRead, BufReader};
-use std::path::PathBuf;
 use std::thread::{self, sleep};
 use std::time::Duration;
-use maplit::hashmap;
 
-use crate::types::TextBlock;
+use crate::screenHelper::Screen;
+use crate::transportHelper::Transport;
 
-const PAD_TO_LEN: Option<usize> = None; // e.g. Some(64) if fixed-size reports are required
-const PREPEND_REPORT_ID_00: bool = false; // set true if reports must start with 0x00
+fn get_payload_from_screen(screen: &Screen) -> Vec<u8>{
+    let mut payload: Vec<u8> = Vec::with_capacity(screen.cells().len() * 3);
+    for cell in screen.cells(){
+        let [lo, hi] = cell.style.encode();
+        let byte = if cell.ch.is_ascii() { cell.ch as u8 } else { b'?' };
 
-
-fn fix_text_length(mut block: Vec<TextBlock>) -> Vec<TextBlock>{
-    let max_len = 24*14;
-    let total_len: usize = block.iter().map(|element| element.text.len()).sum();
-
-    // Cuts off at the end
-    if total_len > max_len{
-        let mut remaining: usize = max_len;
-        for(_, element) in block.iter_mut().enumerate(){
-            let s: &str = &element.text;
-
-            if remaining <= 0 {
-                element.text = String::from("");
-                continue;
-            }
-
-            if s.len() > remaining{
-                element.text = s.chars().take(remaining).collect();
-                remaining = 0;
-            }
-            else{
-                remaining -= s.len();
-            }
-        }
-    }
-    // Pads the end if too short
-    else if total_len < max_len{
-        let spaces_needed = max_len - total_len;
-        if let Some(last) = block.last_mut(){
-            last.text.push_str(&" ".repeat(spaces_needed));
-        }
-    }
-    return block;
-}
-
-fn get_payload_from_blocks(blocks: &Vec<TextBlock>) -> Vec<u8>{
-    let fg_lookup = hashmap! {
-        String::from("orange") => 0,
-        String::from("white") => 1,
-        String::from("cyan") => 2,
-        String::from("green") => 3,
-        String::from("magenta") => 4,
-        String::from("red") => 5,
-        String::from("yellow") => 6
-    };
-
-    let bg_lookup = hashmap!{
-        String::from("black") => 0,
-        String::from("green") => 1,
-        String::from("gray") => 2,
-        String::from("orange") => 3,
-        String::from("purple") => 4
-    };
-
-    // pass cloned version of referenced block to fix_text_length
-    // and then continue working with that
-    let fixed_block: Vec<TextBlock> = fix_text_length(blocks.clone());
-
-    let mut payload: Vec<u8> = Vec::new();
-    for element in fixed_block{
-        let mut fg: &String = &element.fg.clone();
-        let mut bg: &String = &element.bg.clone();
-
-        let white: String = String::from("white");
-        let black: String = String::from("black");
-
-        fg = if fg_lookup.contains_key(fg) { fg } else { &white };
-        bg = if bg_lookup.contains_key(bg) { bg } else { &black };
-
-        let prefix_byte: u8 = 0x21 + 0x21 * fg_lookup[fg] + 0xC * bg_lookup[bg];
-
-        let text_bytes: Vec<u8> = element.text
-            .chars()
-            .map(|c| if c.is_ascii() { c as u8 } else { b'?' })
-            .collect();
-
-        let mut block_playload: Vec<u8> = Vec::new();
-        for byte in text_bytes.iter(){
-            block_playload.extend([prefix_byte, 0x00, *byte])
-        }
-
-        payload.extend(block_playload)
+        payload.extend([lo, hi, byte]);
     }
 
-    return payload
+    payload
 }
 
-fn text_to_hex_packet(blocks: &Vec<TextBlock>) -> Vec<Vec<u8>>{
+pub(crate) fn text_to_hex_packet(screen: &Screen) -> Vec<Vec<u8>>{
     let mut packets: Vec<Vec<u8>> = Vec::new();
-    let payload: Vec<u8> = get_payload_from_blocks(blocks);
+    let payload: Vec<u8> = get_payload_from_screen(screen);
 
     let mut i = 0;
     while i < payload.len(){
@@ -123,86 +40,156 @@ fn text_to_hex_packet(blocks: &Vec<TextBlock>) -> Vec<Vec<u8>>{
 
         packets.push(packet);
     }
-    return packets;
+    packets
 }
 
-pub fn send_text_to_disp(device: &HidDevice,write_delay: f32, blocks: &Vec<TextBlock>) {
-    let hex_packets = text_to_hex_packet(blocks);
-    for (_, element )in hex_packets.iter().enumerate(){
-        #[warn(unused_must_use)]
-        let _ = device.write(&element);
-        thread::sleep(Duration::from_secs_f32(write_delay));
+// Remembers the packets that are currently on the display, so only what changed
+// is written again. Every write costs WRITE_DELAY_LONG of sleep.
+// The 0xF2 report has no cell offset, the display keeps writing where the last
+// packet stopped and wraps around after the last cell. A changed frame is sent
+// from that chunk on up to the last chunk that differs, the unchanged ones before
+// it are sent too so every chunk lands in its place.
+pub struct DisplayWriter {
+    last_packets: Vec<Vec<u8>>,
+    // chunk of the frame the display writes next
+    next_chunk: usize
+}
 
+impl DisplayWriter {
+    pub fn new() -> Self {
+        DisplayWriter { last_packets: Vec::new(), next_chunk: 0 }
     }
-}
 
-pub fn send_init_from_file(device: &HidDevice, init_path: &str, delay_secs: f32) {
-    let mut current_dir: PathBuf = env::current_dir().unwrap();
-    current_dir.push(init_path);
+    // Next frame is written in full, e.g. after the device was reconnected
+    pub fn reset(&mut self) {
+        self.last_packets.clear();
+        self.next_chunk = 0;
+    }
 
-    let file = match File::open(&current_dir) {
-        Ok(f) => f,
-        Err(e) => {
-            eprintln!("Failed to open init file: {}",e);
-            return;
+    pub fn send_text_to_disp<T: Transport + ?Sized>(&mut self, device: &T, write_delay: f32, screen: &Screen) -> Result<()> {
+        let hex_packets = text_to_hex_packet(screen);
+        if hex_packets == self.last_packets {
+            return Ok(());
         }
-    };
-
-    let reader = BufReader::new(file);
-    let delay = Duration::from_secs_f32(delay_secs.max(0.0));
 
-    for (lineno, line_res) in reader.lines().enumerate() {
-        let line_no = lineno + 1;
-        let line = match line_res {
-            Ok(l) => l,
-            Err(e) => {
-                eprintln!("Line {}: read error: {}", line_no, e);
-                continue;
-            }
+        let count = hex_packets.len();
+        let start = if self.last_packets.len() == count { self.next_chunk } else { 0 };
+        // chunks in the order the display takes them, starting at its cursor
+        let order: Vec<usize> = (0..count).map(|i| (start + i) % count).collect();
+        let sent = match order.iter().rposition(|&i| self.last_packets.get(i) != Some(&hex_packets[i])) {
+            Some(last) => last + 1,
+            None => count,
         };
 
-        // Strip comments and whitespace.
-        let trimmed = line.split('#').next().unwrap_or("").trim();
-        if trimmed.is_empty() {
-            continue; // skip blank/comment-only lines
+        for &i in &order[..sent]{
+            if let Err(e) = device.write(&hex_packets[i]) {
+                // whatever is on the display now is unknown, the next frame goes out in full
+                self.reset();
+                return Err(e);
+            }
+            thread::sleep(Duration::from_secs_f32(write_delay));
         }
+        self.next_chunk = (start + sent) % count;
+        self.last_packets = hex_packets;
+        Ok(())
+    }
+}
 
-        // Remove all whitespace to allow "AA BB CC" or "AABBCC" styles.
-        let hex: String = trimmed.split_whitespace().collect();
+// Stops at the first failed write, a partial font upload has to be redone anyway
+pub fn send_init_packets<T: Transport + ?Sized>(device: &T, packets: &[Vec<u8>], delay_secs: f32) -> Result<()> {
+    let delay = Duration::from_secs_f32(delay_secs.max(0.0));
+    for (i, packet) in packets.iter().enumerate() {
+        device.write(packet).with_context(|| format!("Init packet {}: HID write failed", i))?;
+        sleep(delay);
+    }
+    Ok(())
+}
 
-        if hex.len() % 2 != 0 {
-            eprintln!("Line {}: odd number of hex digits: '{}'", line_no, trimmed);
-            continue;
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulatorHelper::Emulator;
+    use crate::transportHelper::MockTransport;
+    use std::sync::Mutex;
+    use crate::types::{Bg, Fg, TextStyle};
+
+    fn screen(text: &str) -> Screen {
+        let mut screen = Screen::new();
+        screen.put_left(0, text, &TextStyle::new(Fg::Green, Bg::Black));
+        screen
+    }
 
-        // Convert hex string to bytes.
-        let mut bytes = Vec::with_capacity(hex.len() / 2);
-        let mut bad_pair = false;
-        for i in (0..hex.len()).step_by(2) {
-            match u8::from_str_radix(&hex[i..i + 2], 16) {
-                Ok(b) => bytes.push(b),
-                Err(e) => {
-                    eprintln!(
-                        "Line {}: invalid hex pair '{}': {}",
-                        line_no,
-                        &hex[i..i + 2],
-                        e
-                    );
-                    bad_pair = true;
-                    break;
-                }
-            }
-        }
-        if bad_pair {
-            continue;
-        }
+    #[test]
+    fn unchanged_frame_is_not_written_again() {
+        let device = MockTransport::new();
+        let mut writer = DisplayWriter::new();
+        writer.send_text_to_disp(&device, 0.0, &screen("WAYPT")).unwrap();
+        // 24x14 cells of 3 bytes in 63 byte packets
+        assert_eq!(device.written().len(), 16);
+        assert!(device.written().iter().all(|p| p.len() == 64 && p[0] == 0xF2));
+
+        device.clear_written();
+        writer.send_text_to_disp(&device, 0.0, &screen("WAYPT")).unwrap();
+        assert!(device.written().is_empty());
+    }
+
+    #[test]
+    fn only_the_chunks_up_to_the_change_are_written() {
+        let device = MockTransport::new();
+        let mut writer = DisplayWriter::new();
+        writer.send_text_to_disp(&device, 0.0, &screen("WAYPT")).unwrap();
+        device.clear_written();
+        // row 0 is in the first chunk
+        writer.send_text_to_disp(&device, 0.0, &screen("STEER")).unwrap();
+        assert_eq!(device.written(), text_to_hex_packet(&screen("STEER"))[..1]);
+    }
 
-        // Send to the HID device.
-        if let Err(e) = device.write(&bytes) {
-            eprintln!("Line {}: HID write failed: {}", line_no, e);
+    // Whatever chunks get skipped, the display ends up with the frame
+    #[test]
+    fn partial_updates_keep_the_display_in_step() {
+        let emulator = Mutex::new(Emulator::new());
+        let mut writer = DisplayWriter::new();
+        let style = TextStyle::new(Fg::Green, Bg::Black);
+        let mut screen = Screen::new();
+        for (row, text) in [(0, "A"), (13, "LAST ROW"), (5, "MIDDLE"), (1, "B"), (13, "AGAIN"), (6, "C")] {
+            screen.put_left(row, text, &style);
+            writer.send_text_to_disp(&emulator, 0.0, &screen).unwrap();
+            assert_eq!(emulator.lock().unwrap().screen(), &screen, "after row {}", row);
         }
+        // less than a full frame for every change
+        assert!(emulator.lock().unwrap().reports() < 16 * 6);
+    }
 
-        // Required delay after every send.
-        sleep(delay);
+    #[test]
+    fn reset_writes_the_same_frame_again() {
+        let device = MockTransport::new();
+        let mut writer = DisplayWriter::new();
+        writer.send_text_to_disp(&device, 0.0, &screen("WAYPT")).unwrap();
+        device.clear_written();
+        writer.reset();
+        writer.send_text_to_disp(&device, 0.0, &screen("WAYPT")).unwrap();
+        assert_eq!(device.written().len(), 16);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn failed_write_sends_the_frame_again() {
+        let device = MockTransport::new();
+        let mut writer = DisplayWriter::new();
+        device.set_unplugged(true);
+        assert!(writer.send_text_to_disp(&device, 0.0, &screen("WAYPT")).is_err());
+        device.set_unplugged(false);
+        writer.send_text_to_disp(&device, 0.0, &screen("WAYPT")).unwrap();
+        assert_eq!(device.written().len(), 16);
+    }
+
+    #[test]
+    fn init_packets_go_out_in_order() {
+        let device = MockTransport::new();
+        let packets = vec![vec![0xF0, 0x00, 0x01], vec![0xF0, 0x00, 0x02]];
+        send_init_packets(&device, &packets, 0.0).unwrap();
+        assert_eq!(device.written(), packets);
+
+        device.set_unplugged(true);
+        assert!(send_init_packets(&device, &packets, 0.0).is_err());
+    }
+}